@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS users(
+	-- canonical, as returned by user.getInfo
+	name TEXT NOT NULL UNIQUE,
+	name_lower TEXT NOT NULL UNIQUE,
+
+	realname TEXT NOT NULL,
+	country TEXT NOT NULL,
+	-- unix timestamp
+	registered INTEGER NOT NULL,
+	playcount INTEGER NOT NULL,
+
+	-- rows older than a day are considered stale
+	date_added TEXT NOT NULL,
+
+	PRIMARY KEY (name)
+);
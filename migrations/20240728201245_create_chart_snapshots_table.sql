@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS chart_snapshots(
+	-- canonical
+	username TEXT NOT NULL,
+	-- as in `Period::to_string`
+	period TEXT NOT NULL,
+	date_fetched TEXT NOT NULL,
+
+	artist TEXT NOT NULL,
+	rank INTEGER NOT NULL,
+	playcount INTEGER NOT NULL,
+
+	-- at most one snapshot per day; later fetches on the same day replace
+	-- earlier ones
+	PRIMARY KEY (username, period, date_fetched, artist)
+);
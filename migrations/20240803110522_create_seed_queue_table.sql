@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS seed_queue(
+	-- as returned by last.fm (i.e. canonical)
+	artist TEXT NOT NULL,
+	-- the tag whose top artists this artist descends from
+	tag TEXT NOT NULL,
+	-- 0 for top artists of a tag, 1 for their similar artists, etc
+	depth INTEGER NOT NULL,
+	-- 'pending', 'done' or 'failed'
+	status TEXT NOT NULL,
+	date_added TEXT NOT NULL,
+
+	-- an artist is only ever seeded once, via the shallowest path
+	PRIMARY KEY (artist)
+);
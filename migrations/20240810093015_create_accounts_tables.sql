@@ -0,0 +1,44 @@
+-- only used if `server.accounts` is enabled; otherwise the single key in
+-- api_key is used
+CREATE TABLE IF NOT EXISTS accounts(
+	id INTEGER NOT NULL,
+	name TEXT NOT NULL UNIQUE,
+	-- argon2, PHC string format
+	password_hash TEXT NOT NULL,
+
+	-- both optional; fall back to lastfm.default_user and the api_key table
+	lastfm_user TEXT,
+	api_key TEXT,
+
+	date_added TEXT NOT NULL,
+
+	PRIMARY KEY (id)
+);
+
+CREATE TABLE IF NOT EXISTS sessions(
+	-- random, stored in a cookie
+	token TEXT NOT NULL,
+	account_id INTEGER NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+	date_added TEXT NOT NULL,
+
+	PRIMARY KEY (token)
+);
+
+-- favourites and ignored artists
+CREATE TABLE IF NOT EXISTS account_artists(
+	account_id INTEGER NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+	artist TEXT NOT NULL,
+	-- 'favourite' or 'ignored'
+	list TEXT NOT NULL,
+	date_added TEXT NOT NULL,
+
+	PRIMARY KEY (account_id, artist, list)
+);
+
+-- trees viewed, most recent last
+CREATE TABLE IF NOT EXISTS history(
+	account_id INTEGER NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+	artist TEXT NOT NULL,
+	-- datetime, not date, so that visits on the same day can be ordered
+	date_visited TEXT NOT NULL
+);
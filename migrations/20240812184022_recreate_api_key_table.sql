@@ -0,0 +1,17 @@
+-- the key is now encrypted with server.secret (see secret.rs), unless no secret
+-- is set. since encryption is randomised, a UNIQUE constraint on the stored
+-- value no longer prevents duplicates, so the single row is enforced by id
+-- instead. existing (plaintext) keys are carried over, and encrypted at the
+-- next startup
+CREATE TABLE IF NOT EXISTS api_key_new(
+	id INTEGER PRIMARY KEY CHECK (id = 1),
+	key TEXT NOT NULL,
+	date_added DATE DEFAULT CURRENT_DATE
+);
+
+INSERT INTO api_key_new(id, key)
+SELECT 1, key FROM api_key LIMIT 1;
+
+DROP TABLE api_key;
+
+ALTER TABLE api_key_new RENAME TO api_key;
@@ -0,0 +1,10 @@
+-- session keys obtained via auth.getSession (see auth.rs); they do not expire
+CREATE TABLE IF NOT EXISTS lastfm_sessions(
+	-- Last.fm username, as returned by auth.getSession
+	name TEXT NOT NULL PRIMARY KEY,
+	-- encrypted if server.secret is set
+	session_key TEXT NOT NULL,
+	-- NULL if connected without an account (e.g. via /settings)
+	account_id INTEGER REFERENCES accounts(id) ON DELETE CASCADE,
+	date_added DATE DEFAULT CURRENT_DATE
+);
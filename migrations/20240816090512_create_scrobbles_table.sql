@@ -0,0 +1,12 @@
+-- imported scrobble history (see scrobbles.rs), used for local charts. a
+-- scrobble is identified by its time, so re-importing is harmless
+CREATE TABLE IF NOT EXISTS scrobbles(
+	-- Last.fm username (not necessarily canonical)
+	user TEXT NOT NULL,
+	artist TEXT NOT NULL,
+	track TEXT NOT NULL,
+	album TEXT,
+	-- unix time
+	timestamp INTEGER NOT NULL,
+	PRIMARY KEY (user, timestamp, artist, track)
+);
@@ -0,0 +1,10 @@
+-- a user's top artists overall (see User::get_library), used to mark known
+-- artists in trees. rows are considered stale after cache.user_info_days
+CREATE TABLE IF NOT EXISTS libraries(
+	-- lowercase Last.fm username
+	user TEXT NOT NULL,
+	-- json object of lowercase artist -> playcount
+	artists TEXT NOT NULL,
+	date_added TEXT NOT NULL,
+	PRIMARY KEY (user)
+);
@@ -0,0 +1,4 @@
+-- the depth up to which the job that queued an artist expands its similar
+-- artists; queueing an artist again with a greater max_depth (or via a
+-- shallower path) sets it back to pending (see enqueue_seed)
+ALTER TABLE seed_queue ADD COLUMN max_depth INTEGER NOT NULL DEFAULT 0;
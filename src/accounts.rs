@@ -0,0 +1,347 @@
+//! Module for optional user accounts, enabled with `server.accounts`. Each
+//! account can store its own Last.fm username and API key, and keeps its own
+//! favourites, ignored artists and history.
+//!
+//! Without accounts (the default), the single key in the `api_key` table and
+//! `lastfm.default_user` are used, which is enough for solo self-hosting.
+
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::future::Future;
+use std::pin::Pin;
+
+use actix_web::cookie::Cookie;
+use actix_web::cookie::SameSite;
+use actix_web::dev::Payload;
+use actix_web::error::ErrorUnauthorized;
+use actix_web::web;
+use actix_web::FromRequest;
+use actix_web::HttpRequest;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::SaltString;
+use argon2::Argon2;
+use argon2::PasswordHash;
+use argon2::PasswordHasher;
+use argon2::PasswordVerifier;
+use maud::html;
+use maud::Markup;
+use urlencoding::encode;
+
+use crate::auth::LastfmAuth;
+use crate::get_lastfm_sessions;
+use crate::html;
+use crate::secret::mask;
+use crate::SqPool;
+
+pub const SESSION_COOKIE: &str = "lasttree_session";
+
+/// Number of entries shown on the account page
+const HISTORY_SIZE: u32 = 50;
+
+#[derive(Debug, Clone)]
+pub struct Account {
+    pub id: i64,
+    pub name: String,
+    pub lastfm_user: Option<String>,
+    pub api_key: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArtistList {
+    Favourites,
+    /// Never shown in trees
+    Ignored,
+}
+
+impl Display for ArtistList {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        // stored in db as is
+        let s = match self {
+            Self::Favourites => "favourite",
+            Self::Ignored => "ignored",
+        };
+        write!(f, "{s}")
+    }
+}
+
+impl TryFrom<&str> for ArtistList {
+    type Error = String;
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "favourite" => Ok(Self::Favourites),
+            "ignored" => Ok(Self::Ignored),
+            _ => Err(format!("Invalid list: {value}")),
+        }
+    }
+}
+
+fn hash_password(password: &str) -> anyhow::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow::anyhow!("Could not hash password: {e}"))?;
+    Ok(hash.to_string())
+}
+
+fn verify_password(
+    password: &str,
+    hash: &str,
+) -> bool {
+    PasswordHash::new(hash)
+        .is_ok_and(|h| Argon2::default().verify_password(password.as_bytes(), &h).is_ok())
+}
+
+impl Account {
+    /// Usernames may only contain ASCII letters, digits, `-` and `_`;
+    /// passwords must be at least 8 characters long.
+    pub async fn register(
+        pool: &SqPool,
+        name: &str,
+        password: &str,
+    ) -> anyhow::Result<i64> {
+        let name = name.trim();
+        if name.is_empty()
+            || name.len() > 32
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            anyhow::bail!("Usernames must be 1 to 32 letters, digits, - or _");
+        }
+        if password.chars().count() < 8 {
+            anyhow::bail!("Passwords must be at least 8 characters long");
+        }
+        if Self::get_password_hash(pool, name).await?.is_some() {
+            anyhow::bail!("Username already taken: {name}");
+        }
+
+        let hash = hash_password(password)?;
+        Ok(Self::create(pool, name, &hash).await?)
+    }
+
+    /// Returns a new session token. The error message deliberately does not
+    /// distinguish between unknown users and wrong passwords.
+    pub async fn login(
+        pool: &SqPool,
+        name: &str,
+        password: &str,
+    ) -> anyhow::Result<String> {
+        match Self::get_password_hash(pool, name.trim()).await? {
+            Some((id, hash)) if verify_password(password, &hash) => {
+                Ok(Self::create_session(pool, id).await?)
+            }
+            _ => anyhow::bail!("Invalid username or password"),
+        }
+    }
+
+    pub fn session_cookie(token: &str) -> Cookie<'static> {
+        Cookie::build(SESSION_COOKIE, token.to_string())
+            .path("/")
+            .http_only(true)
+            .same_site(SameSite::Lax)
+            .permanent()
+            .finish()
+    }
+
+    /// Lowercase, for comparison with tree nodes
+    pub async fn get_ignored(
+        &self,
+        pool: &SqPool,
+    ) -> sqlx::Result<HashSet<String>> {
+        let ignored = self.get_artists(pool, ArtistList::Ignored).await?;
+        Ok(ignored.into_iter().map(|a| a.to_lowercase()).collect())
+    }
+
+    /// `message` (e.g. a rejected API key) is shown above the settings
+    pub async fn as_html(
+        &self,
+        pool: &SqPool,
+        message: Option<&str>,
+    ) -> sqlx::Result<Markup> {
+        let favourites = self.get_artists(pool, ArtistList::Favourites).await?;
+        let ignored = self.get_artists(pool, ArtistList::Ignored).await?;
+        let history = self.get_history(pool, HISTORY_SIZE).await?;
+        let sessions = match LastfmAuth::from_config() {
+            Some(_) => Some(get_lastfm_sessions(pool, Some(self.id)).await?),
+            None => None,
+        };
+
+        let artist_list = |list: ArtistList, artists: &[String]| {
+            html! {
+                @if artists.is_empty() {
+                    p { "None yet." }
+                }
+                ul {
+                    @for artist in artists {
+                        li {
+                            (html::link(&format!("/artists/{}", encode(artist)), artist))
+                            " "
+                            form
+                                style="display: inline"
+                                method="POST"
+                                action=(format!("/account/{list}/{}/remove", encode(artist)))
+                                { button type="submit" { "Remove" } }
+                        }
+                    }
+                }
+            }
+        };
+
+        let html = html! {
+            (html::header(&format!("Account: {}", self.name)))
+            form method="POST" action="/account/logout" {
+                button type="submit" { "Log out" }
+            }
+
+            h2 { "Settings" }
+            @if let Some(message) = message {
+                p { b { (message) } }
+            }
+            form method="POST" action="/account" {
+                label { "Last.fm username: "
+                    input
+                        type="text"
+                        name="lastfm_user"
+                        value=(self.lastfm_user.clone().unwrap_or_default())
+                        {}
+                }
+                br;
+                // the key itself is never sent back to the browser
+                label { "Last.fm API key: "
+                    input
+                        type="password"
+                        name="api_key"
+                        placeholder=(self.api_key.as_deref().map(mask).unwrap_or("(none)".to_string()))
+                        {}
+                }
+                " (leave empty to keep the current key)"
+                br;
+                @if self.api_key.is_some() {
+                    label {
+                        input type="checkbox" name="delete_key" value="true" {}
+                        " Remove API key"
+                    }
+                    br;
+                }
+                button type="submit" { "Save" }
+            }
+
+            @if let Some(sessions) = sessions {
+                (html::lastfm_sessions(&sessions))
+            }
+
+            h2 { "Favourites" }
+            (artist_list(ArtistList::Favourites, &favourites))
+
+            h2 { "Ignored" }
+            (artist_list(ArtistList::Ignored, &ignored))
+
+            h2 { "History" }
+            ul {
+                @for (artist, date) in history {
+                    li {
+                        (date) " "
+                        (html::link(&format!("/artists/{}", encode(&artist)), &artist))
+                    }
+                }
+            }
+        };
+        Ok(html)
+    }
+}
+
+/// Login/registration form, with an optional error message
+pub fn account_form(
+    title: &str,
+    action: &str,
+    error: Option<&str>,
+) -> Markup {
+    html! {
+        (html::header(title))
+        @if let Some(error) = error {
+            p { b { (error) } }
+        }
+        form method="POST" action=(action) {
+            label { "Username: "
+                input required type="text" name="name" autofocus="true" {}
+            }
+            br;
+            label { "Password: "
+                input required type="password" name="password" {}
+            }
+            br;
+            button type="submit" { (title) }
+        }
+        @if action == "/account/login" {
+            p { "No account? " (html::link("/account/register", "Register")) }
+        } @else {
+            p { "Already registered? " (html::link("/account/login", "Log in")) }
+        }
+    }
+}
+
+/// Extract the logged-in account from the session cookie. Use
+/// `Option<Account>` in handlers where logging in is optional.
+impl FromRequest for Account {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(
+        req: &HttpRequest,
+        _: &mut Payload,
+    ) -> Self::Future {
+        let token = req.cookie(SESSION_COOKIE).map(|c| c.value().to_string());
+        let pool = req.app_data::<web::Data<SqPool>>().cloned();
+
+        Box::pin(async move {
+            let (Some(token), Some(pool)) = (token, pool) else {
+                return Err(ErrorUnauthorized("Not logged in"));
+            };
+            Account::from_session(&pool, &token)
+                .await
+                .map_err(crate::error_500)?
+                .ok_or(ErrorUnauthorized("Not logged in"))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Account;
+    use super::ArtistList;
+    use crate::tests::TestPool;
+
+    #[tokio::test]
+    async fn register_and_login() {
+        let pool = &TestPool::new(None).await.pool;
+
+        assert!(Account::register(pool, "bad name", "password").await.is_err());
+        assert!(Account::register(pool, "alice", "short").await.is_err());
+
+        Account::register(pool, "alice", "correct horse").await.unwrap();
+        assert!(Account::register(pool, "alice", "correct horse").await.is_err());
+
+        assert!(Account::login(pool, "alice", "wrong horse").await.is_err());
+        let token = Account::login(pool, "alice", "correct horse").await.unwrap();
+
+        let account = Account::from_session(pool, &token).await.unwrap().unwrap();
+        assert_eq!(account.name, "alice");
+
+        account
+            .add_artist(pool, ArtistList::Ignored, "Metallica")
+            .await
+            .unwrap();
+        assert!(account.get_ignored(pool).await.unwrap().contains("metallica"));
+        assert!(account
+            .get_artists(pool, ArtistList::Favourites)
+            .await
+            .unwrap()
+            .is_empty());
+
+        Account::delete_session(pool, &token).await.unwrap();
+        assert!(Account::from_session(pool, &token).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,763 @@
+//! Versioned JSON API, mounted at `/api/v1/` (see `config`). Handlers call the
+//! same domain functions as the HTML routes in `routes`, and accept the same
+//! query params.
+//!
+//! All errors have the body
+//!
+//! ```json
+//! { "error": { "status": 404, "code": "user_not_found", "message": "User not found: foo" } }
+//! ```
+//!
+//! where `status` and `code` are derived from `LastfmError` (if applicable).
+
+use actix_web::delete;
+use actix_web::get;
+use actix_web::http::StatusCode;
+use actix_web::post;
+use actix_web::web;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::ResponseError;
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::Value;
+
+use crate::accounts::Account;
+use crate::accounts::ArtistList;
+use crate::artists::Artist;
+use crate::artists::LastfmError;
+use crate::charts::Period;
+use crate::charts::User;
+use crate::compare::Comparison;
+use crate::get_genre;
+use crate::get_top_genres;
+use crate::lastfm_key;
+use crate::profile::TagProfile;
+use crate::recommend::Recommendations;
+use crate::routes::for_account;
+use crate::routes::ChartPageQuery;
+use crate::routes::ChartWindowQuery;
+use crate::routes::PageQuery;
+use crate::routes::TreeQuery;
+use crate::stats::Stats;
+use crate::tag_graph::TagGraph;
+use crate::tag_graph::TagSource;
+use crate::trends::Trends;
+use crate::ArtistTree;
+use crate::SqPool;
+
+/// Number of history entries returned by `/account/history`
+const HISTORY_SIZE: u32 = 100;
+
+#[derive(thiserror::Error, Debug)]
+#[error("{message}")]
+pub struct ApiError {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+}
+
+impl ApiError {
+    fn new(
+        status: StatusCode,
+        code: &'static str,
+        message: impl ToString,
+    ) -> Self {
+        Self {
+            status,
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    fn bad_request(message: impl ToString) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "bad_request", message)
+    }
+
+    fn not_found(message: impl ToString) -> Self {
+        Self::new(StatusCode::NOT_FOUND, "not_found", message)
+    }
+
+    fn unauthorized() -> Self {
+        Self::new(StatusCode::UNAUTHORIZED, "unauthorized", "Not logged in")
+    }
+
+    pub fn code(&self) -> &str {
+        self.code
+    }
+}
+
+/// Same status codes as the HTML routes (see `impl ResponseError for
+/// LastfmError`)
+impl From<LastfmError> for ApiError {
+    fn from(e: LastfmError) -> Self {
+        let code = match &e {
+            LastfmError::NoApiKey => "no_api_key",
+            LastfmError::ParseError(_) => "not_found",
+            LastfmError::DatabaseError(_) => "database_error",
+            LastfmError::NetworkError(_) => "network_error",
+            LastfmError::UserNotFound(_) => "user_not_found",
+            LastfmError::NotCached(_) => "not_cached",
+            LastfmError::Other(_) => "internal_error",
+        };
+        Self::new(e.status_code(), code, e)
+    }
+}
+
+/// Errors that wrap a `LastfmError` (e.g. from `Artist::get_listeners`) keep its
+/// status and code
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        match e.downcast::<LastfmError>() {
+            Ok(e) => e.into(),
+            Err(e) => Self::new(StatusCode::INTERNAL_SERVER_ERROR, "internal_error", e),
+        }
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(e: sqlx::Error) -> Self {
+        LastfmError::from(e).into()
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status).json(json!({
+            "error": {
+                "status": self.status.as_u16(),
+                "code": self.code,
+                "message": self.message,
+            }
+        }))
+    }
+}
+
+type ApiResult = Result<web::Json<Value>, ApiError>;
+
+/// Register all routes; to be mounted with `web::scope("/api/v1")`
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_artist)
+        .service(get_similar)
+        .service(get_tags)
+        .service(get_tree)
+        .service(get_path)
+        .service(get_seed_tree)
+        .service(get_genres)
+        .service(get_genre_artists)
+        .service(get_tag_graph)
+        .service(get_user)
+        // like the HTML routes, must be registered before `get_chart`
+        .service(get_chart_window)
+        .service(get_chart_local)
+        .service(get_trends)
+        .service(get_chart)
+        .service(get_profile)
+        .service(get_stats)
+        .service(get_recommendations)
+        .service(get_comparison)
+        .service(get_history)
+        .service(get_saved)
+        .service(add_saved)
+        .service(remove_saved)
+        .default_service(web::route().to(not_found));
+}
+
+/// Unlike the HTML routes, which redirect to `/`
+async fn not_found(req: HttpRequest) -> Result<HttpResponse, ApiError> {
+    Err(ApiError::not_found(format!("No such endpoint: {}", req.path())))
+}
+
+// artists {{{
+
+#[derive(Deserialize)]
+struct ArtistQuery {
+    /// Use cached data only
+    #[serde(default)]
+    offline: bool,
+}
+
+/// Apply the logged-in account's API key (if any)
+fn artist(
+    name: &str,
+    query: &ArtistQuery,
+    account: Option<&Account>,
+) -> Artist {
+    Artist::new(name)
+        .with_key(account.and_then(|a| a.api_key.clone()))
+        .with_offline(query.offline)
+}
+
+/// `{ name, listeners, tags }`, where `name` is canonical if the artist has
+/// been cached
+#[get("/artists/{artist}")]
+async fn get_artist(
+    path: web::Path<String>,
+    query: web::Query<ArtistQuery>,
+    pool: web::Data<SqPool>,
+    account: Option<Account>,
+) -> ApiResult {
+    let artist = artist(&path, &query, account.as_ref());
+    let listeners = artist.get_listeners(&pool).await?;
+    let tags = artist.get_tags(&pool).await?;
+    let name = artist.canonical_name(&pool).await?.unwrap_or(path.into_inner());
+    Ok(web::Json(json!({
+        "name": name,
+        "listeners": listeners,
+        "tags": tags,
+    })))
+}
+
+/// `[{ name, similarity }]`, by descending similarity (max 100)
+#[get("/artists/{artist}/similar")]
+async fn get_similar(
+    path: web::Path<String>,
+    query: web::Query<ArtistQuery>,
+    pool: web::Data<SqPool>,
+    account: Option<Account>,
+) -> ApiResult {
+    let similars = artist(&path, &query, account.as_ref())
+        .get_similar_artists(&pool)
+        .await?;
+    let similars: Vec<Value> = similars
+        .into_iter()
+        .map(|(name, sim)| json!({ "name": name, "similarity": sim }))
+        .collect();
+    Ok(web::Json(json!(similars)))
+}
+
+#[get("/artists/{artist}/tags")]
+async fn get_tags(
+    path: web::Path<String>,
+    query: web::Query<ArtistQuery>,
+    pool: web::Data<SqPool>,
+    account: Option<Account>,
+) -> ApiResult {
+    let tags = artist(&path, &query, account.as_ref())
+        .get_tags(&pool)
+        .await?;
+    Ok(web::Json(json!(tags)))
+}
+//}}}
+// trees {{{
+
+/// Nodes (in table order, seeds first) and edges of a built tree. `known_plays`
+/// is only set for artists in the user's library (see `TreeQuery`).
+fn tree_json(tree: &ArtistTree) -> Value {
+    let mut nodes: Vec<&String> = tree.nodes().collect();
+    nodes.sort_by_key(|n| (!tree.is_seed(n), -tree.get_seed_similarity(n)));
+    let nodes: Vec<Value> = nodes
+        .into_iter()
+        .map(|n| {
+            json!({
+                "name": n,
+                "seed": tree.is_seed(n),
+                "similarity": tree.get_seed_similarity(n),
+                "known_plays": tree.known_plays(n),
+                "uncached": tree.is_uncached(n),
+            })
+        })
+        .collect();
+    json!({
+        "seeds": tree.seeds(),
+        "nodes": nodes,
+        "edges": graph_edges(&tree.graph),
+        "pruned": tree.pruned(),
+    })
+}
+
+fn graph_edges(graph: &petgraph::Graph<String, i64>) -> Vec<Value> {
+    graph
+        .raw_edges()
+        .iter()
+        .map(|e| {
+            json!({
+                "source": graph[e.source()],
+                "target": graph[e.target()],
+                "similarity": e.weight,
+            })
+        })
+        .collect()
+}
+
+/// Build a tree from `seeds`, with the same params as `/artists/{artist}`
+async fn build_tree(
+    tree: ArtistTree,
+    req: &HttpRequest,
+    pool: &SqPool,
+    account: Option<&Account>,
+) -> Result<ArtistTree, ApiError> {
+    let query = TreeQuery::parse(req).map_err(ApiError::bad_request)?;
+    let tree = for_account(tree, account, pool).await?;
+    Ok(query.build_tree(tree, account, pool).await?)
+}
+
+#[get("/artists/{artist}/tree")]
+async fn get_tree(
+    path: web::Path<String>,
+    req: HttpRequest,
+    pool: web::Data<SqPool>,
+    account: Option<Account>,
+) -> ApiResult {
+    let tree = build_tree(ArtistTree::new(&path), &req, &pool, account.as_ref()).await?;
+    Ok(web::Json(tree_json(&tree)))
+}
+
+/// `/trees?seed=A&seed=B`
+#[get("/trees")]
+async fn get_seed_tree(
+    req: HttpRequest,
+    pool: web::Data<SqPool>,
+    account: Option<Account>,
+) -> ApiResult {
+    let query = TreeQuery::parse(&req).map_err(ApiError::bad_request)?;
+    if query.seed.is_empty() {
+        return Err(ApiError::bad_request("At least one seed is required"));
+    }
+    let tree = ArtistTree::from_seeds(&query.seed);
+    let tree = build_tree(tree, &req, &pool, account.as_ref()).await?;
+    Ok(web::Json(tree_json(&tree)))
+}
+
+#[derive(Deserialize)]
+struct PathPath {
+    artist: String,
+    target: String,
+}
+
+/// How `target` is reached in the tree of `artist`: `{ similarity, path: [{
+/// name, similarity }] }`, where each similarity in `path` is relative to the
+/// previous artist
+#[get("/artists/{artist}/path/{target}")]
+async fn get_path(
+    path: web::Path<PathPath>,
+    req: HttpRequest,
+    pool: web::Data<SqPool>,
+    account: Option<Account>,
+) -> ApiResult {
+    let tree = build_tree(ArtistTree::new(&path.artist), &req, &pool, account.as_ref()).await?;
+    let Some(nodes) = tree.get_path(&path.target) else {
+        return Err(ApiError::not_found(format!(
+            "{} is not in the tree of {}",
+            path.target, path.artist
+        )));
+    };
+    let nodes: Vec<Value> = nodes
+        .into_iter()
+        .map(|(name, sim)| json!({ "name": name, "similarity": sim }))
+        .collect();
+    Ok(web::Json(json!({
+        "similarity": tree.get_seed_similarity(&path.target),
+        "path": nodes,
+    })))
+}
+//}}}
+// genres {{{
+
+#[get("/genres")]
+async fn get_genres(
+    query: web::Query<PageQuery>,
+    pool: web::Data<SqPool>,
+    account: Option<Account>,
+) -> ApiResult {
+    let page = query.page.unwrap_or(1).max(1);
+    let key = account.and_then(|a| a.api_key);
+    let key = lastfm_key(&pool, key.as_deref()).await?;
+    let genres = get_top_genres(page, &key).await?;
+    let genres: Vec<Value> = genres
+        .0
+        .iter()
+        .map(|g| json!({ "name": g.name.to_lowercase(), "taggings": g.taggings.parse::<u64>().ok() }))
+        .collect();
+    Ok(web::Json(json!({ "page": page, "genres": genres })))
+}
+
+#[get("/genres/{genre}")]
+async fn get_genre_artists(
+    path: web::Path<String>,
+    query: web::Query<PageQuery>,
+    pool: web::Data<SqPool>,
+    account: Option<Account>,
+) -> ApiResult {
+    let page = query.page.unwrap_or(1).max(1);
+    let key = account.and_then(|a| a.api_key);
+    let key = lastfm_key(&pool, key.as_deref()).await?;
+    let genre = get_genre(&path, page, &key).await?;
+    let artists: Vec<&str> = genre.artists.iter().map(|a| a.name.as_str()).collect();
+    Ok(web::Json(json!({
+        "name": genre.name,
+        "page": genre.page,
+        "total_pages": genre.total_pages,
+        "artists": artists,
+        "similar": genre.similar,
+    })))
+}
+
+#[derive(Deserialize)]
+struct TagGraphQuery {
+    #[serde(default)]
+    source: TagSource,
+    depth: Option<u32>,
+}
+
+#[get("/genres/{genre}/graph")]
+async fn get_tag_graph(
+    path: web::Path<String>,
+    query: web::Query<TagGraphQuery>,
+    pool: web::Data<SqPool>,
+    account: Option<Account>,
+) -> ApiResult {
+    let mut graph = TagGraph::new(&path)
+        .with_source(query.source)
+        .with_key(account.and_then(|a| a.api_key));
+    if let Some(depth) = query.depth {
+        graph = graph.with_depth(depth);
+    }
+    let graph = graph.build(&pool).await?;
+    let nodes: Vec<&String> = graph.graph.node_weights().collect();
+    Ok(web::Json(json!({
+        "nodes": nodes,
+        "edges": graph_edges(&graph.graph),
+    })))
+}
+//}}}
+// charts {{{
+
+#[derive(Deserialize)]
+struct ChartPath {
+    user: String,
+    period: String,
+}
+
+fn parse_period(period: &str) -> Result<Period, ApiError> {
+    Period::try_from(period).map_err(ApiError::bad_request)
+}
+
+/// `{ name, realname, country, registered, playcount }`
+#[get("/users/{user}")]
+async fn get_user(
+    path: web::Path<String>,
+    pool: web::Data<SqPool>,
+    account: Option<Account>,
+) -> ApiResult {
+    let info = User::new(&path, account.as_ref(), &pool).await?.info;
+    Ok(web::Json(json!({
+        "name": info.name,
+        "realname": info.realname,
+        "country": info.country,
+        "registered": info.registered,
+        "playcount": info.playcount,
+    })))
+}
+
+/// Unlike the HTML route, an invalid period is an error
+#[get("/charts/{user}/{period}")]
+async fn get_chart(
+    path: web::Path<ChartPath>,
+    query: web::Query<ChartPageQuery>,
+    pool: web::Data<SqPool>,
+    account: Option<Account>,
+) -> ApiResult {
+    let period = parse_period(&path.period)?;
+    let user = User::new(&path.user, account.as_ref(), &pool).await?;
+    let chart = user
+        .get_chart_period(period, query.limit(), query.page())
+        .await?;
+    Ok(web::Json(chart.as_json()))
+}
+
+/// `/charts/{user}/custom?from=2024-01-01&to=2024-02-01`
+#[get("/charts/{user}/custom")]
+async fn get_chart_window(
+    path: web::Path<String>,
+    query: web::Query<ChartWindowQuery>,
+    page: web::Query<ChartPageQuery>,
+    pool: web::Data<SqPool>,
+    account: Option<Account>,
+) -> ApiResult {
+    let user = User::new(&path, account.as_ref(), &pool).await?;
+    let (from, to) = query.window().map_err(ApiError::bad_request)?;
+    let chart = user
+        .get_chart_window(from, to, page.limit(), page.page())
+        .await?;
+    Ok(web::Json(chart.as_json()))
+}
+
+/// Computed from imported scrobbles (see `scrobbles`)
+#[get("/charts/{user}/local")]
+async fn get_chart_local(
+    path: web::Path<String>,
+    query: web::Query<ChartWindowQuery>,
+    page: web::Query<ChartPageQuery>,
+    pool: web::Data<SqPool>,
+    account: Option<Account>,
+) -> ApiResult {
+    let user = User::new(&path, account.as_ref(), &pool).await?;
+    let (from, to) = query.window().map_err(ApiError::bad_request)?;
+    let chart = user
+        .get_local_chart(from, to, page.limit(), page.page(), &pool)
+        .await?;
+    Ok(web::Json(chart.as_json()))
+}
+
+#[derive(Deserialize)]
+struct TrendsQuery {
+    period: Option<String>,
+}
+
+/// `/charts/{user}/trends?period=7day`; see `Trends`
+#[get("/charts/{user}/trends")]
+async fn get_trends(
+    path: web::Path<String>,
+    query: web::Query<TrendsQuery>,
+    pool: web::Data<SqPool>,
+    account: Option<Account>,
+) -> ApiResult {
+    let period = match &query.period {
+        Some(p) => parse_period(p)?,
+        None => Period::default(),
+    };
+    let user = User::new(&path, account.as_ref(), &pool).await?;
+    let trends = Trends::new(user.username(), period, &pool).await?;
+    Ok(web::Json(trends.as_json()))
+}
+
+/// Tag profile of the user's top artists (default limit: 50)
+#[get("/charts/{user}/{period}/tags")]
+async fn get_profile(
+    path: web::Path<ChartPath>,
+    query: web::Query<ChartPageQuery>,
+    pool: web::Data<SqPool>,
+    account: Option<Account>,
+) -> ApiResult {
+    let period = parse_period(&path.period)?;
+    let limit = query.limit.unwrap_or(50);
+    let profile = TagProfile::new(&path.user, period, limit, account.as_ref(), &pool).await?;
+    Ok(web::Json(profile.as_json()))
+}
+
+/// Computed from imported scrobbles only, so the user is not looked up on
+/// Last.fm
+#[get("/stats/{user}")]
+async fn get_stats(
+    path: web::Path<String>,
+    pool: web::Data<SqPool>,
+) -> ApiResult {
+    let stats = Stats::new(&path, &pool).await?;
+    Ok(web::Json(stats.as_json()))
+}
+
+#[get("/recommend/{user}/{period}")]
+async fn get_recommendations(
+    path: web::Path<ChartPath>,
+    query: web::Query<ChartPageQuery>,
+    pool: web::Data<SqPool>,
+    account: Option<Account>,
+) -> ApiResult {
+    let period = parse_period(&path.period)?;
+    let recs =
+        Recommendations::new(&path.user, period, query.limit(), account.as_ref(), &pool).await?;
+    Ok(web::Json(recs.as_json()))
+}
+
+#[derive(Deserialize)]
+struct ComparePath {
+    user_a: String,
+    user_b: String,
+    period: String,
+}
+
+#[get("/compare/{user_a}/{user_b}/{period}")]
+async fn get_comparison(
+    path: web::Path<ComparePath>,
+    pool: web::Data<SqPool>,
+    account: Option<Account>,
+) -> ApiResult {
+    let period = parse_period(&path.period)?;
+    let comparison =
+        Comparison::new(&path.user_a, &path.user_b, period, account.as_ref(), &pool).await?;
+    Ok(web::Json(comparison.as_json()))
+}
+//}}}
+// saved items {{{
+
+// all require a logged-in account (i.e. the session cookie)
+
+/// `[{ artist, date_visited }]`, most recent first
+// must be registered before `get_saved`, otherwise "history" is parsed as a
+// list
+#[get("/account/history")]
+async fn get_history(
+    account: Option<Account>,
+    pool: web::Data<SqPool>,
+) -> ApiResult {
+    let account = account.ok_or(ApiError::unauthorized())?;
+    let history: Vec<Value> = account
+        .get_history(&pool, HISTORY_SIZE)
+        .await?
+        .into_iter()
+        .map(|(artist, date)| json!({ "artist": artist, "date_visited": date }))
+        .collect();
+    Ok(web::Json(json!(history)))
+}
+
+fn parse_list(list: &str) -> Result<ArtistList, ApiError> {
+    ArtistList::try_from(list).map_err(ApiError::bad_request)
+}
+
+/// `/account/favourite` or `/account/ignored`; most recently added first
+#[get("/account/{list}")]
+async fn get_saved(
+    path: web::Path<String>,
+    account: Option<Account>,
+    pool: web::Data<SqPool>,
+) -> ApiResult {
+    let account = account.ok_or(ApiError::unauthorized())?;
+    let artists = account.get_artists(&pool, parse_list(&path)?).await?;
+    Ok(web::Json(json!(artists)))
+}
+
+#[derive(Deserialize)]
+struct SavedPath {
+    list: String,
+    artist: String,
+}
+
+#[post("/account/{list}/{artist}")]
+async fn add_saved(
+    path: web::Path<SavedPath>,
+    account: Option<Account>,
+    pool: web::Data<SqPool>,
+) -> ApiResult {
+    let account = account.ok_or(ApiError::unauthorized())?;
+    let list = parse_list(&path.list)?;
+    account.add_artist(&pool, list, &path.artist).await?;
+    Ok(web::Json(json!(account.get_artists(&pool, list).await?)))
+}
+
+#[delete("/account/{list}/{artist}")]
+async fn remove_saved(
+    path: web::Path<SavedPath>,
+    account: Option<Account>,
+    pool: web::Data<SqPool>,
+) -> ApiResult {
+    let account = account.ok_or(ApiError::unauthorized())?;
+    let list = parse_list(&path.list)?;
+    account.remove_artist(&pool, list, &path.artist).await?;
+    Ok(web::Json(json!(account.get_artists(&pool, list).await?)))
+}
+//}}}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::body::to_bytes;
+    use actix_web::http::Method;
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use actix_web::web;
+    use actix_web::ResponseError;
+    use serde_json::json;
+    use serde_json::Value;
+
+    use super::ApiError;
+    use crate::artists::LastfmError;
+    use crate::charts::UserInfo;
+    use crate::config::Config;
+    use crate::init_app;
+    use crate::tests::TestPool;
+
+    #[tokio::test]
+    async fn error_bodies() {
+        let err = ApiError::from(LastfmError::UserNotFound("foo".to_string()));
+        assert_eq!(err.status_code(), StatusCode::NOT_FOUND);
+
+        let body = to_bytes(err.error_response().into_body()).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            json!({ "error": { "status": 404, "code": "user_not_found", "message": "User not found: foo" } })
+        );
+
+        // wrapped errors keep their code
+        let err = ApiError::from(anyhow::Error::from(LastfmError::NotCached("foo".to_string())));
+        assert_eq!(err.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(err.code(), "not_cached");
+
+        let err = ApiError::from(anyhow::anyhow!("oops"));
+        assert_eq!(err.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(err.code(), "internal_error");
+    }
+
+    /// `/charts/{user}/{period}` must not shadow the fixed chart routes
+    #[actix_web::test]
+    async fn chart_routes() {
+        let pool = TestPool::new(None).await;
+        // `store_api_key` would validate the key
+        sqlx::query("INSERT INTO api_key (id, key) VALUES (1, 'key')")
+            .execute(&pool.pool)
+            .await
+            .unwrap();
+        // cached, so that the user is not looked up on Last.fm
+        let info = UserInfo {
+            name: "foo".to_string(),
+            realname: String::new(),
+            country: "None".to_string(),
+            registered: 0,
+            playcount: 0,
+        };
+        info.store(&pool.pool).await.unwrap();
+
+        let app = init_app(
+            web::Data::new(pool.pool.clone()),
+            web::Data::new(Config::default()),
+        );
+        let app = test::init_service(app).await;
+        let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+
+        for uri in ["/api/v1/charts/foo/local", "/api/v1/charts/foo/trends"] {
+            let resp = test::call_service(&app, get(uri)).await;
+            assert_eq!(resp.status(), StatusCode::OK, "{uri}");
+        }
+
+        // an invalid date, not an invalid period
+        let resp = test::call_service(&app, get("/api/v1/charts/foo/custom?from=foo")).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: Value = test::read_body_json(resp).await;
+        assert_ne!(body["error"]["message"], "Invalid: custom");
+
+        let resp = test::call_service(&app, get("/api/v1/charts/foo/foo")).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["message"], "Invalid: foo");
+    }
+
+    #[actix_web::test]
+    async fn account_unauthorized() {
+        let pool = TestPool::new(None).await;
+        let app = init_app(
+            web::Data::new(pool.pool.clone()),
+            web::Data::new(Config::default()),
+        );
+        let app = test::init_service(app).await;
+
+        for (method, uri) in [
+            (Method::GET, "/api/v1/account/history"),
+            (Method::GET, "/api/v1/account/favourite"),
+            (Method::POST, "/api/v1/account/favourite/foo"),
+            (Method::DELETE, "/api/v1/account/ignored/foo"),
+        ] {
+            let req = test::TestRequest::default()
+                .method(method)
+                .uri(uri)
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED, "{uri}");
+            let body: Value = test::read_body_json(resp).await;
+            assert_eq!(body["error"]["code"], "unauthorized");
+        }
+    }
+}
@@ -4,7 +4,9 @@
 
 use std::f64;
 
+use actix_web::http::header::ContentType;
 use actix_web::http::StatusCode;
+use actix_web::HttpResponse;
 use actix_web::ResponseError;
 use indexmap::IndexMap;
 use serde::de;
@@ -14,13 +16,24 @@ use serde_json::Value;
 
 use crate::get_api_key;
 use crate::get_json;
+use crate::html;
+use crate::lastfm_key;
 use crate::utils::build_lastfm_url;
+use crate::utils::fetch;
 use crate::SqPool;
-use crate::LASTFM_KEY;
+use crate::OFFLINE;
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Artist {
     pub name: String,
+
+    /// If true, only cached data is returned; see `with_offline`
+    #[serde(skip)]
+    offline: bool,
+
+    /// Overrides the key in the `api_key` table; see `with_key`
+    #[serde(skip)]
+    key: Option<String>,
 }
 
 // https://stackoverflow.com/a/75684771
@@ -57,6 +70,17 @@ pub enum LastfmError {
 
     #[error(transparent)]
     NetworkError(#[from] reqwest::Error),
+
+    #[error("User not found: {0}")]
+    UserNotFound(String),
+
+    /// Only returned in offline mode
+    #[error("Not cached: {0}")]
+    NotCached(String),
+
+    /// e.g. a stored key that could not be decrypted
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
 }
 
 impl ResponseError for LastfmError {
@@ -64,9 +88,28 @@ impl ResponseError for LastfmError {
         match self {
             Self::NoApiKey => StatusCode::UNAUTHORIZED,
             Self::ParseError(_) => StatusCode::NOT_FOUND, // TODO: is there a better code?
+            Self::UserNotFound(_) => StatusCode::NOT_FOUND,
+            Self::NotCached(_) => StatusCode::SERVICE_UNAVAILABLE,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut resp = HttpResponse::build(self.status_code());
+        match self {
+            // typos in /charts/{user} are common enough to deserve a proper page
+            Self::UserNotFound(_) => {
+                let html = maud::html! {
+                    (html::header("Charts"))
+                    p { (self) }
+                    p { (html::link("/charts/", "Return")) }
+                };
+                resp.content_type(ContentType::html()).body(html.into_string())
+            }
+            // default impl
+            _ => resp.content_type(ContentType::plaintext()).body(self.to_string()),
+        }
+    }
 }
 
 /// A convenience struct used when iterating over a json array
@@ -94,9 +137,34 @@ impl Artist {
     pub fn new(name: &str) -> Self {
         Self {
             name: name.to_string(),
+            offline: false,
+            key: None,
         }
     }
 
+    /// Use an account's own API key (if any) instead of the shared one
+    pub fn with_key(
+        mut self,
+        key: Option<String>,
+    ) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// In offline mode, `get_similar_artists`, `get_listeners` and `get_tags`
+    /// never make network requests, and return `LastfmError::NotCached` if
+    /// there is no cached data. Offline mode is always on if `$LASTTREE_OFFLINE`
+    /// is set.
+    pub fn with_offline(
+        mut self,
+        offline: bool,
+    ) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    pub fn is_offline(&self) -> bool { self.offline || *OFFLINE }
+
     /// Important: a Last.fm API key is required
     ///
     /// Fetches from db if `self.name` has been cached in the `artists` table.
@@ -120,14 +188,21 @@ impl Artist {
             }
         }
 
-        let key = get_api_key(pool).await?.ok_or(LastfmError::NoApiKey)?;
+        if self.is_offline() {
+            return Err(LastfmError::NotCached(self.name.clone()));
+        }
+
+        let key = match &self.key {
+            Some(key) => key.clone(),
+            None => get_api_key(pool).await?.ok_or(LastfmError::NoApiKey)?,
+        };
 
         let url = build_lastfm_url("artist.getsimilar", &key, &[("artist", &self.name)]).unwrap();
 
         // TODO: NotFound variant is somewhere here...
 
         // String -> Value -> struct
-        let resp = reqwest::get(url).await?.text().await?;
+        let resp = fetch(url).await?;
         let json: Value = serde_json::from_str(&resp)?;
         let json = &json["similarartists"];
 
@@ -159,7 +234,12 @@ impl Artist {
             return Ok(x.try_into()?);
         };
 
-        let url = build_lastfm_url("artist.getinfo", &LASTFM_KEY, &[("artist", &self.name)])?;
+        if self.is_offline() {
+            return Err(LastfmError::NotCached(self.name.clone()).into());
+        }
+
+        let key = lastfm_key(pool, self.key.as_deref()).await?;
+        let url = build_lastfm_url("artist.getinfo", &key, &[("artist", &self.name)])?;
         let json = get_json(url.as_ref()).await?;
 
         // println!("{:#?}", json);
@@ -184,7 +264,12 @@ impl Artist {
             return Ok(tags);
         };
 
-        let url = build_lastfm_url("artist.getinfo", &LASTFM_KEY, &[("artist", &self.name)])?;
+        if self.is_offline() {
+            return Err(LastfmError::NotCached(self.name.clone()).into());
+        }
+
+        let key = lastfm_key(pool, self.key.as_deref()).await?;
+        let url = build_lastfm_url("artist.getinfo", &key, &[("artist", &self.name)])?;
         let json = get_json(url.as_ref()).await?;
 
         let tags: Vec<Value> = serde_json::from_value(json["artist"]["tags"]["tag"].clone())?;
@@ -0,0 +1,282 @@
+//! Module for authenticating as a Last.fm user, which requires the shared
+//! secret of the API account (`lastfm.shared_secret`). Two flows are supported:
+//!
+//! - web: the user is sent to `authorize_url(None, Some(callback))`, and
+//!   Last.fm redirects back to `callback` with a `token` param
+//! - desktop (`lasttree auth`): a token is requested with `get_token`, the
+//!   user visits `authorize_url(Some(token), None)`, then returns to the
+//!   terminal
+//!
+//! In both cases, the token is exchanged for a session key with `get_session`.
+//! Session keys do not expire, and are stored (encrypted, if `server.secret` is
+//! set) in the `lastfm_sessions` table.
+//!
+//! https://www.last.fm/api/authentication
+
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::config::config;
+use crate::utils::throttle;
+
+/// Page where users grant access to the application
+const AUTH_URL: &str = "https://www.last.fm/api/auth/";
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct LastfmSession {
+    /// Last.fm username
+    pub name: String,
+    pub key: String,
+}
+
+/// https://www.last.fm/api/authspec#_8-signing-calls
+///
+/// `format` and `callback` are not signed.
+pub fn api_sig(
+    params: &[(&str, &str)],
+    secret: &str,
+) -> String {
+    let mut params: Vec<_> = params
+        .iter()
+        .filter(|(k, _)| !matches!(*k, "format" | "callback"))
+        .collect();
+    params.sort_by_key(|(k, _)| *k);
+
+    let mut s: String = params.iter().map(|(k, v)| format!("{k}{v}")).collect();
+    s.push_str(secret);
+    format!("{:x}", md5::compute(s))
+}
+
+pub struct LastfmAuth {
+    base_url: String,
+    auth_url: String,
+    key: String,
+    secret: String,
+}
+
+impl LastfmAuth {
+    pub fn new(
+        base_url: &str,
+        key: &str,
+        secret: &str,
+    ) -> Self {
+        Self {
+            base_url: base_url.to_string(),
+            auth_url: AUTH_URL.to_string(),
+            key: key.to_string(),
+            secret: secret.to_string(),
+        }
+    }
+
+    /// `None` if `lastfm.key` or `lastfm.shared_secret` is not set
+    pub fn from_config() -> Option<Self> {
+        let lastfm = &config().lastfm;
+        Some(Self::new(
+            &lastfm.base_url,
+            lastfm.key.as_deref()?,
+            lastfm.shared_secret.as_deref()?,
+        ))
+    }
+
+    /// Default: `AUTH_URL`
+    pub fn with_auth_url(
+        mut self,
+        url: &str,
+    ) -> Self {
+        self.auth_url = url.to_string();
+        self
+    }
+
+    /// `params` with `method`, `api_key`, `sk` (if given) and `api_sig` added
+    fn signed_params(
+        &self,
+        method: &str,
+        params: &[(&str, &str)],
+        session_key: Option<&str>,
+    ) -> Vec<(String, String)> {
+        let mut all: Vec<(&str, &str)> = vec![("method", method), ("api_key", &self.key)];
+        if let Some(sk) = session_key {
+            all.push(("sk", sk));
+        }
+        all.extend_from_slice(params);
+
+        let sig = api_sig(&all, &self.secret);
+        all.into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .chain([("api_sig".to_string(), sig)])
+            .collect()
+    }
+
+    /// Signed equivalent of `build_lastfm_url`, for GET requests
+    pub fn signed_url(
+        &self,
+        method: &str,
+        params: &[(&str, &str)],
+        session_key: Option<&str>,
+    ) -> anyhow::Result<Url> {
+        let params = self.signed_params(method, params, session_key);
+        Ok(Url::parse_with_params(&self.base_url, params)?)
+    }
+
+    /// Send a (rate-limited) request, turning Last.fm errors into `Err`
+    async fn send(
+        &self,
+        req: reqwest::RequestBuilder,
+    ) -> anyhow::Result<Value> {
+        throttle().await;
+        let text = req.send().await?.text().await?;
+        let json: Value = serde_json::from_str(&text)?;
+
+        // https://www.last.fm/api/errorcodes
+        if let Some(code) = json["error"].as_u64() {
+            let msg = json["message"].as_str().unwrap_or("unknown error");
+            anyhow::bail!("Last.fm error {code}: {msg}");
+        }
+        Ok(json)
+    }
+
+    /// https://www.last.fm/api/show/auth.getToken
+    pub async fn get_token(&self) -> anyhow::Result<String> {
+        let url = self.signed_url("auth.getToken", &[], None)?;
+        let json = self.send(reqwest::Client::new().get(url)).await?;
+        json["token"]
+            .as_str()
+            .map(|t| t.to_string())
+            .ok_or(anyhow::anyhow!("No token in response"))
+    }
+
+    /// Page where the user grants access. In the desktop flow, `token` is the
+    /// one from `get_token`; in the web flow, Last.fm redirects to `callback`
+    /// with a new token.
+    pub fn authorize_url(
+        &self,
+        token: Option<&str>,
+        callback: Option<&str>,
+    ) -> anyhow::Result<Url> {
+        let mut url = Url::parse_with_params(&self.auth_url, [("api_key", &self.key)])?;
+        if let Some(token) = token {
+            url.query_pairs_mut().append_pair("token", token);
+        }
+        if let Some(cb) = callback {
+            url.query_pairs_mut().append_pair("cb", cb);
+        }
+        Ok(url)
+    }
+
+    /// Exchange an authorised token for a session key. Tokens are valid for 60
+    /// minutes, and can only be used once.
+    ///
+    /// https://www.last.fm/api/show/auth.getSession
+    pub async fn get_session(
+        &self,
+        token: &str,
+    ) -> anyhow::Result<LastfmSession> {
+        let url = self.signed_url("auth.getSession", &[("token", token)], None)?;
+        let json = self.send(reqwest::Client::new().get(url)).await?;
+        Ok(serde_json::from_value(json["session"].clone())?)
+    }
+
+    /// https://www.last.fm/api/show/track.love
+    pub async fn love_track(
+        &self,
+        session: &LastfmSession,
+        artist: &str,
+        track: &str,
+    ) -> anyhow::Result<()> {
+        // write methods must be POSTed; `format` is already in `base_url`
+        let params = self.signed_params(
+            "track.love",
+            &[("artist", artist), ("track", track)],
+            Some(&session.key),
+        );
+        let req = reqwest::Client::new().post(&self.base_url).form(&params);
+        self.send(req).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::matchers::body_string_contains;
+    use wiremock::matchers::method;
+    use wiremock::matchers::query_param;
+    use wiremock::Mock;
+    use wiremock::MockServer;
+    use wiremock::ResponseTemplate;
+
+    use super::api_sig;
+    use super::LastfmAuth;
+    use super::LastfmSession;
+
+    #[test]
+    fn signature() {
+        // order of params is irrelevant; format is ignored
+        let sig = api_sig(
+            &[("token", "t"), ("method", "auth.getSession"), ("api_key", "k"), ("format", "json")],
+            "s",
+        );
+        assert_eq!(sig, format!("{:x}", md5::compute("api_keykmethodauth.getSessiontokents")));
+    }
+
+    #[tokio::test]
+    async fn auth_flow() {
+        let server = MockServer::start().await;
+        let auth = LastfmAuth::new(&format!("{}/2.0/?format=json", server.uri()), "key", "secret");
+
+        Mock::given(method("GET"))
+            .and(query_param("method", "auth.getToken"))
+            .and(query_param("api_sig", api_sig(&[("method", "auth.getToken"), ("api_key", "key")], "secret")))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"token":"abc"}"#))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        Mock::given(method("GET"))
+            .and(query_param("method", "auth.getSession"))
+            .and(query_param("token", "abc"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"session":{"name":"alice","key":"sk123","subscriber":0}}"#,
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        Mock::given(method("GET"))
+            .and(query_param("method", "auth.getSession"))
+            .and(query_param("token", "unauthorised"))
+            .respond_with(ResponseTemplate::new(403).set_body_string(
+                r#"{"error":14,"message":"Unauthorized Token - This token has not been authorized"}"#,
+            ))
+            .mount(&server)
+            .await;
+
+        Mock::given(method("POST"))
+            .and(body_string_contains("method=track.love"))
+            .and(body_string_contains("sk=sk123"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let token = auth.get_token().await.unwrap();
+        assert_eq!(token, "abc");
+
+        let url = auth.authorize_url(Some(&token), None).unwrap();
+        assert_eq!(url.as_str(), "https://www.last.fm/api/auth/?api_key=key&token=abc");
+
+        let session = auth.get_session(&token).await.unwrap();
+        assert_eq!(
+            session,
+            LastfmSession {
+                name: "alice".to_string(),
+                key: "sk123".to_string()
+            }
+        );
+
+        let err = auth.get_session("unauthorised").await.unwrap_err();
+        assert!(err.to_string().contains("error 14"));
+
+        auth.love_track(&session, "Loona", "Butterfly").await.unwrap();
+    }
+}
@@ -1,38 +1,192 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
 use std::fmt::Display;
 
+use chrono::DateTime;
 use maud::html;
 use maud::Markup;
+use reqwest::Url;
 use serde::de;
 use serde::Deserialize;
 use serde::Deserializer;
+use serde_json::json;
 use serde_json::Value;
 use strum::IntoEnumIterator;
 
 // use crate::artists;
+use crate::accounts::Account;
 use crate::artists::Artist;
+use crate::artists::LastfmError;
+use crate::auth::LastfmAuth;
+use crate::config::config;
+use crate::get_cached_library;
+use crate::get_lastfm_session;
+use crate::get_scrobble_counts;
 use crate::html;
+use crate::lastfm_key;
+use crate::store_library;
 use crate::utils::build_lastfm_url;
+use crate::utils::fetch;
 use crate::utils::human_number;
 use crate::SqPool;
-use crate::LASTFM_KEY;
+use crate::OFFLINE;
+use crate::WEEK;
+
+/// Number of artists per chart page, unless specified otherwise
+pub const DEFAULT_LIMIT: u32 = 10;
 
 // TODO: unify User and Chart structs?
 
 pub struct User {
     username: String,
+    pub info: UserInfo,
+    /// If the user has connected their Last.fm account (see `auth`), requests
+    /// are signed, so that private profiles can be accessed
+    session: Option<String>,
+    key: String,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct Chart {
     #[serde(rename = "artist")]
-    artists: Vec<ChartArtist>,
+    pub(crate) artists: Vec<ChartArtist>,
+
+    #[serde(skip)]
+    pub(crate) period: Period,
 
+    /// Only set for charts obtained via `get_chart_window`, in which case
+    /// `period` is meaningless
     #[serde(skip)]
-    period: Period,
+    window: Option<ChartRange>,
+
+    /// Computed from imported scrobbles (see `scrobbles`), rather than
+    /// obtained from Last.fm
+    #[serde(skip)]
+    local: bool,
+
+    /// Not returned by user.getWeeklyArtistChart, so this is filled in manually
+    /// for windowed charts
+    #[serde(rename = "@attr", default)]
+    meta: ChartMeta,
+
+    /// Total plays in the period (or window), used to calculate each artist's
+    /// share of plays
+    #[serde(skip)]
+    plays: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct ChartMeta {
+    #[serde(default, deserialize_with = "str_to_u64")]
+    page: u64,
+
+    #[serde(rename = "perPage", default, deserialize_with = "str_to_u64")]
+    per_page: u64,
+
+    #[serde(rename = "totalPages", default, deserialize_with = "str_to_u64")]
+    total_pages: u64,
+
+    /// Note: this is the number of artists, not the number of plays
+    #[serde(default, deserialize_with = "str_to_u64")]
+    total: u64,
+}
+
+/// https://www.last.fm/api/show/user.getInfo
+#[derive(Deserialize, Debug)]
+pub struct UserInfo {
+    /// Canonical
+    pub name: String,
+
+    /// May be empty
+    pub realname: String,
+
+    /// "None" if not set
+    pub country: String,
+
+    /// Unix timestamp
+    #[serde(deserialize_with = "extract_unixtime")]
+    pub registered: u64,
+
+    #[serde(deserialize_with = "str_to_u64")]
+    pub playcount: u64,
+}
+
+/// A pair of timestamps, as returned by user.getWeeklyChartList
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ChartRange {
+    #[serde(deserialize_with = "str_to_u64")]
+    pub from: u64,
+    #[serde(deserialize_with = "str_to_u64")]
+    pub to: u64,
+}
+
+impl ChartRange {
+    /// If a window is not specified, the last week (7 days) is used; if only
+    /// one end is specified, the other is 7 days away.
+    pub fn new(
+        from: Option<u64>,
+        to: Option<u64>,
+    ) -> anyhow::Result<Self> {
+        let week = *WEEK as u64;
+
+        let now = chrono::Utc::now().timestamp() as u64;
+        let window = match (from, to) {
+            (Some(from), Some(to)) => ChartRange { from, to },
+            (Some(from), None) => ChartRange {
+                from,
+                to: from + week,
+            },
+            (None, Some(to)) => ChartRange {
+                from: to.saturating_sub(week),
+                to,
+            },
+            (None, None) => ChartRange {
+                from: now - week,
+                to: now,
+            },
+        };
+        anyhow::ensure!(window.from < window.to, "Invalid window: {window}");
+        Ok(window)
+    }
+}
+
+impl Display for ChartRange {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        let date = |ts: u64| {
+            DateTime::from_timestamp(ts as i64, 0)
+                .map(|dt| dt.format("%Y-%m-%d").to_string())
+                .unwrap_or_default()
+        };
+        write!(f, "{} to {}", date(self.from), date(self.to))
+    }
+}
+
+/// Widen the window to the nearest boundaries in `ranges` (which are assumed
+/// to be sorted), such that the whole window is covered. Boundaries outside
+/// `ranges` are clamped.
+fn snap_window(
+    ranges: &[ChartRange],
+    window: ChartRange,
+) -> Option<ChartRange> {
+    let from = ranges
+        .iter()
+        .rev()
+        .find(|r| r.from <= window.from)
+        .or(ranges.first())?
+        .from;
+    let to = ranges
+        .iter()
+        .find(|r| r.to >= window.to)
+        .or(ranges.last())?
+        .to;
+    Some(ChartRange { from, to })
 }
 
 #[derive(Deserialize, Debug)]
-struct ChartArtist {
+pub(crate) struct ChartArtist {
     pub name: String,
 
     #[serde(deserialize_with = "str_to_u64")]
@@ -49,24 +203,136 @@ impl ChartArtist {
     // proper way to share methods is to use a trait
     async fn get_listeners(
         &self,
+        key: &str,
         pool: &SqPool,
     ) -> anyhow::Result<u32> {
-        Artist::new(&self.name).get_listeners(pool).await
+        Artist::new(&self.name)
+            .with_key(Some(key.to_string()))
+            .get_listeners(pool)
+            .await
     }
     async fn get_tags(
         &self,
+        key: &str,
         pool: &SqPool,
     ) -> anyhow::Result<Vec<String>> {
-        Artist::new(&self.name).get_tags(pool).await
+        Artist::new(&self.name)
+            .with_key(Some(key.to_string()))
+            .get_tags(pool)
+            .await
     }
 }
 
 impl Chart {
+    /// Chart of artists with the given playcounts (in descending order),
+    /// computed from imported scrobbles
+    fn from_local(
+        counts: Vec<(String, i64)>,
+        window: ChartRange,
+    ) -> Self {
+        let artists = counts
+            .into_iter()
+            .enumerate()
+            .map(|(i, (name, plays))| ChartArtist {
+                name,
+                playcount: plays as u64,
+                rank: i as u64 + 1,
+            })
+            .collect();
+        Chart {
+            artists,
+            period: Period::default(),
+            window: Some(window),
+            local: true,
+            meta: ChartMeta::default(),
+            plays: None,
+        }
+    }
+
+    /// Total plays of the chart's period, used for the share of each artist.
+    /// Windowed charts already have this.
+    pub fn with_plays(
+        mut self,
+        plays: Option<u64>,
+    ) -> Self {
+        self.plays = plays;
+        self
+    }
+
+    /// Last path segment of windowed charts
+    fn window_path(&self) -> &str {
+        match self.local {
+            true => "local",
+            false => "custom",
+        }
+    }
+
+    /// For charts where all artists are obtained at once; `meta` and `plays` are
+    /// filled in accordingly
+    fn paginate(
+        mut self,
+        limit: u32,
+        page: u32,
+    ) -> Self {
+        let (limit, page) = (limit.max(1) as usize, page.max(1) as usize);
+        self.meta = ChartMeta {
+            page: page as u64,
+            per_page: limit as u64,
+            total_pages: self.artists.len().div_ceil(limit) as u64,
+            total: self.artists.len() as u64,
+        };
+        self.plays = Some(self.artists.iter().map(|a| a.playcount).sum());
+        self.artists = self
+            .artists
+            .into_iter()
+            .skip((page - 1) * limit)
+            .take(limit)
+            .collect();
+        self
+    }
+
+    /// Tab-separated `rank`, `artist`, `playcount`, one artist per line, for
+    /// use outside the browser
+    pub fn as_tsv(&self) -> String {
+        self.artists
+            .iter()
+            .map(|a| format!("{}\t{}\t{}", a.rank, a.name, a.playcount))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    /// Used by the JSON API. `period` is omitted for windowed charts, and
+    /// `window` for period charts.
+    pub fn as_json(&self) -> Value {
+        let artists: Vec<Value> = self
+            .artists
+            .iter()
+            .map(|a| json!({ "rank": a.rank, "name": a.name, "playcount": a.playcount }))
+            .collect();
+        let mut json = json!({
+            "local": self.local,
+            "page": self.meta.page,
+            "total_pages": self.meta.total_pages,
+            "total_artists": self.meta.total,
+            "plays": self.plays,
+            "artists": artists,
+        });
+        match self.window {
+            Some(w) => json["window"] = json!({ "from": w.from, "to": w.to }),
+            None => json["period"] = json!(self.period.to_string()),
+        }
+        json
+    }
+
     pub async fn as_html(
         &self,
-        user: &str,
+        user: &User,
         pool: &SqPool,
     ) -> actix_web::Result<Markup> {
+        let info = &user.info;
+        let rows = self.rows_html(user, pool).await;
+        let user = &user.username;
+
         // let library_link = |user: &str, artist: &str| {
         //     format!("https://www.last.fm/user/{user}/library/music/{artist}?date_preset=ALL")
         // };
@@ -89,18 +355,52 @@ impl Chart {
 
         let periods = html! {
             @for p in Period::iter() {
-                @if p == self.period {
+                @if self.window.is_none() && p == self.period {
                     b { (p) }
                 } @else {
                     (html::link(&format!("/charts/{user}/{p}"), &p.to_string()))
                 }
                 " "
             }
+            @if !self.local {
+                (html::link(&format!("/charts/{user}/local"), "local"))
+            }
+        };
+
+        // https://developer.mozilla.org/en-US/docs/Web/HTML/Element/input/date
+        let window_form = html! {
+            form
+                method="GET"
+                action=(format!("/charts/{user}/{}", self.window_path()))
+                {
+                    @if let Some(window) = self.window {
+                        b { (self.window_path()) } " (" (window) ") "
+                    }
+                    label { "From: " input type="date" name="from" {} }
+                    " "
+                    label { "To: " input type="date" name="to" {} }
+                    " "
+                    button type="submit" { "Go" }
+                }
         };
 
         let html = html! {
             (html::header(&format!("Top artists for {user}")))
-            // TODO: total scrobbles
+            p {
+                @if !info.realname.is_empty() {
+                    (info.realname) br;
+                }
+                @if info.country != "None" {
+                    "Country: " (info.country) br;
+                }
+                @if let Some(registered) = DateTime::from_timestamp(info.registered as i64, 0) {
+                    "Registered: " (registered.format("%Y-%m-%d")) br;
+                }
+                "Total scrobbles: " (info.playcount) br;
+                @if let Some(plays) = self.plays {
+                    "Plays in period: " (plays) " (" (self.meta.total) " artists)"
+                }
+            }
 
             form //{{{
                 method="POST"
@@ -117,43 +417,121 @@ impl Chart {
                 }
             }//}}}
 
+            script src="https://unpkg.com/htmx.org@1.9.12" {}
+
             // TODO: htmx tabs?
             // (dropdown)
             (periods)
+            (window_form)
+            @if self.window.is_none() {
+                p {
+                    (html::link(&format!("/recommend/{user}/{}", self.period), "Recommendations"))
+                    " "
+                    (html::link(&format!("/charts/{user}/trends?period={}", self.period), "Trends"))
+                    " "
+                    (html::link(&format!("/charts/{user}/{}/tags", self.period), "Tags"))
+                    " "
+                    (html::link(&format!("/stats/{user}"), "Stats"))
+                }
+            }
+
+            @if self.local && self.artists.is_empty() {
+                p { "No imported scrobbles in this window (see `lasttree import`)." }
+            }
 
             table {
 
                 th {"#"}
                 th {"Artist"}
                 th {"Plays"}
+                th {"Share"}
                 th {"Listeners"}
                 th {"Tags"}
 
-                @for artist in &self.artists {
-                    @let name = &artist.name;
-                    // @let link = library_link(user, name.clone());
-                    @let link = format!("/artists/{name}");
-                    @let cols = vec![
-                        artist.rank.to_string(),
-                        (html::link(&link, name).into()),
-                        artist.playcount.to_string(),
-                        // TODO: plays as % of total plays in the current period
-                        human_number(artist.get_listeners(pool).await.unwrap_or(0)),
-                        artist.get_tags(pool).await.unwrap_or(vec![]).join(", "),
-                    ];
-                    (html::table_row(cols))
+                (rows)
+            }
+        };
+        Ok(html)
+    }
+
+    /// Table rows only (without `<table>`), followed by a row to load the next
+    /// page, if there is one. This is what gets swapped in by htmx.
+    // https://htmx.org/examples/click-to-load/
+    pub async fn rows_html(
+        &self,
+        user: &User,
+        pool: &SqPool,
+    ) -> Markup {
+        let key = &user.key;
+        let user = &user.username;
+        let next_page = match self.meta.page < self.meta.total_pages {
+            false => None,
+            true => {
+                let mut params = format!("limit={}&page={}", self.meta.per_page, self.meta.page + 1);
+                // so that the total is not fetched again for every page
+                if let Some(plays) = self.plays {
+                    params.push_str(&format!("&plays={plays}"));
                 }
+                Some(match self.window {
+                    Some(w) => format!(
+                        "/charts/{user}/{}?from={}&to={}&{params}",
+                        self.window_path(),
+                        w.from,
+                        w.to
+                    ),
+                    None => format!("/charts/{user}/{}?{params}", self.period),
+                })
+            }
+        };
 
-                // @for (c, _) in cols.iter() { th { (c) } }
-                // @for artist in artists {
-                //     (table_row(cols.iter().map(|x| (x.1)(artist)).collect()))
-                // }
+        html! {
+            @for artist in &self.artists {
+                @let name = &artist.name;
+                // @let link = library_link(user, name.clone());
+                @let link = format!("/artists/{name}");
+                @let cols = vec![
+                    artist.rank.to_string(),
+                    (html::link(&link, name).into()),
+                    artist.playcount.to_string(),
+                    self.plays.map(|total| share_bar(artist.playcount, total).into_string()).unwrap_or_default(),
+                    human_number(artist.get_listeners(key, pool).await.unwrap_or(0)),
+                    artist.get_tags(key, pool).await.unwrap_or(vec![]).join(", "),
+                ];
+                (html::table_row(cols))
+            }
+
+            // @for (c, _) in cols.iter() { th { (c) } }
+            // @for artist in artists {
+            //     (table_row(cols.iter().map(|x| (x.1)(artist)).collect()))
+            // }
 
-                // TODO: final row to load next page (hx-swap="afterend")
-                // https://htmx.org/examples/click-to-load/
+            @if let Some(next_page) = next_page {
+                tr id="load-more" {
+                    td colspan="6" {
+                        button
+                            hx-get=(next_page)
+                            hx-target="#load-more"
+                            hx-swap="outerHTML"
+                            { "Load more" }
+                    }
+                }
             }
-        };
-        Ok(html)
+        }
+    }
+}
+
+/// Percentage, followed by a bar of proportional width
+fn share_bar(
+    plays: u64,
+    total: u64,
+) -> Markup {
+    let pct = match total {
+        0 => 0.0,
+        _ => plays as f64 / total as f64 * 100.0,
+    };
+    html! {
+        (format!("{pct:.1}%"))
+        div style=(format!("background: steelblue; height: 0.5em; width: {pct:.1}%")) {}
     }
 }
 
@@ -172,6 +550,21 @@ where
     Ok(val)
 }
 
+/// `{"unixtime": "1037793040", "#text": 1037793040}` -> 1037793040
+fn extract_unixtime<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Value::deserialize(deserializer)? {
+        Value::Object(obj) => obj["unixtime"]
+            .as_str()
+            .ok_or_else(|| de::Error::custom("wrong type"))?
+            .parse()
+            .map_err(de::Error::custom),
+        _ => Err(de::Error::custom("wrong type")),
+    }
+}
+
 fn extract_inner<'de, D>(deserializer: D) -> Result<u64, D::Error>
 where
     D: Deserializer<'de>,
@@ -200,7 +593,7 @@ where
 }
 //}}}
 
-#[derive(Debug, PartialEq, strum_macros::EnumIter)]
+#[derive(Debug, Clone, Copy, PartialEq, strum_macros::EnumIter)]
 pub enum Period {
     //{{{
     Week,
@@ -256,37 +649,127 @@ impl Display for Period {
 }
 //}}}
 
+/// Library of `user` (see `User::get_library`) without any requests: the cached
+/// library, or else the playcounts of imported scrobbles
+pub(crate) async fn get_offline_library(
+    pool: &SqPool,
+    user: &str,
+) -> anyhow::Result<HashMap<String, u64>> {
+    if let Some(library) = get_cached_library(pool, user).await? {
+        return Ok(library);
+    }
+    let counts = get_scrobble_counts(pool, user, 0, i64::MAX as u64).await?;
+    Ok(counts
+        .into_iter()
+        .map(|(name, plays)| (name.to_lowercase(), plays as u64))
+        .collect())
+}
+
 impl User {
-    pub fn new(username: &str) -> anyhow::Result<Self> {
-        // TODO: check whether user exists
+    /// Fails with `LastfmError::UserNotFound` if the user does not exist. User
+    /// info is cached in the `users` table for a day.
+    ///
+    /// Requests use the API key of `account` (the logged-in account), if it has
+    /// one. A session is only used if it was connected by `account`, so that a
+    /// private profile is not visible to everyone else.
+    pub async fn new(
+        username: &str,
+        account: Option<&Account>,
+        pool: &SqPool,
+    ) -> Result<Self, LastfmError> {
+        let session = match account {
+            Some(account) => get_lastfm_session(pool, username, Some(account.id)).await?,
+            // without accounts, sessions connected via `/settings` belong to the
+            // owner of the instance, who is the only user
+            None if !config().server.accounts => get_lastfm_session(pool, username, None).await?,
+            None => None,
+        };
+        Self::with_session(username, account, session, pool).await
+    }
+
+    /// For the CLI, which is only run by the owner of the instance; sessions
+    /// connected without an account (e.g. via `lasttree auth`) are used even if
+    /// accounts are enabled.
+    pub async fn for_owner(
+        username: &str,
+        pool: &SqPool,
+    ) -> Result<Self, LastfmError> {
+        let session = get_lastfm_session(pool, username, None).await?;
+        Self::with_session(username, None, session, pool).await
+    }
+
+    async fn with_session(
+        username: &str,
+        account: Option<&Account>,
+        session: Option<String>,
+        pool: &SqPool,
+    ) -> Result<Self, LastfmError> {
+        let key = account.and_then(|a| a.api_key.as_deref());
+        let key = lastfm_key(pool, key).await?;
+        let info = match UserInfo::get_cached(pool, username).await? {
+            Some(info) => info,
+            None => {
+                let info = Self::get_info(username, &key, session.as_deref()).await?;
+                info.store(pool).await?;
+                info
+            }
+        };
+
         let user = User {
-            username: username.to_string(),
+            username: info.name.clone(),
+            info,
+            session,
+            key,
         };
         Ok(user)
     }
 
+    pub fn username(&self) -> &str { &self.username }
+
+    /// Signed if there is a session (and `lastfm.shared_secret` is set)
+    fn lastfm_url(
+        method: &str,
+        params: &[(&str, &str)],
+        key: &str,
+        session: Option<&str>,
+    ) -> anyhow::Result<Url> {
+        match (session, LastfmAuth::from_config()) {
+            (Some(sk), Some(auth)) => auth.signed_url(method, params, Some(sk)),
+            _ => build_lastfm_url(method, key, params),
+        }
+    }
+
+    pub(crate) fn url(
+        &self,
+        method: &str,
+        params: &[(&str, &str)],
+    ) -> anyhow::Result<Url> {
+        Self::lastfm_url(method, params, &self.key, self.session.as_deref())
+    }
+
     /// Get an artist chart constrained to one of six fixed time periods, as
     /// defined by last.fm (see `Period` for more details).
     ///
     /// https://www.last.fm/api/show/user.getTopArtists
+    ///
+    /// `page` starts from 1.
     pub async fn get_chart_period(
         &self,
         period: Period,
-        // limit: u16,
+        limit: u32,
+        page: u32,
     ) -> anyhow::Result<Chart> {
-        let limit = 10;
-
-        let url = build_lastfm_url(
+        let url = self.url(
             "user.gettopartists",
-            &LASTFM_KEY,
             &[
                 ("limit", &limit.to_string()),
+                ("page", &page.to_string()),
                 ("period", &period.to_string()),
                 ("user", &self.username),
             ],
         )?;
 
-        let json = reqwest::get(url).await?.text().await?;
+        let json = fetch(url).await?;
         let json: Value = serde_json::from_str(&json)?;
         // println!("{:#?}", json);
         let mut chart: Chart = serde_json::from_value(json["topartists"].clone())?;
@@ -297,33 +780,253 @@ impl User {
         Ok(chart)
     }
 
+    /// https://www.last.fm/api/show/user.getInfo
+    async fn get_info(
+        username: &str,
+        key: &str,
+        session: Option<&str>,
+    ) -> Result<UserInfo, LastfmError> {
+        let url = Self::lastfm_url("user.getinfo", &[("user", username)], key, session)?;
+
+        let json = fetch(url).await?;
+        let json: Value = serde_json::from_str(&json)?;
+
+        // https://www.last.fm/api/errorcodes
+        if json["error"].as_u64() == Some(6) {
+            return Err(LastfmError::UserNotFound(username.to_string()));
+        }
+
+        let info = serde_json::from_value(json["user"].clone())?;
+
+        Ok(info)
+    }
+
+    /// Total plays in `period`. Last.fm does not provide this directly
+    /// (`@attr.total` is the number of artists), so, except for
+    /// `Period::Overall` (which is just the user's scrobble count), this is the
+    /// sum of playcounts of all artists, 1000 at a time. This is expensive, so
+    /// it is only used for full chart pages (see `Chart::with_plays`).
+    pub(crate) async fn get_period_plays(
+        &self,
+        period: Period,
+    ) -> anyhow::Result<u64> {
+        if period == Period::Overall {
+            return Ok(self.info.playcount);
+        }
+
+        let mut total = 0;
+        let mut page = 1;
+        loop {
+            let url = self.url(
+                "user.gettopartists",
+                &[
+                    ("limit", "1000"),
+                    ("page", &page.to_string()),
+                    ("period", &period.to_string()),
+                    ("user", &self.username),
+                ],
+            )?;
+            let json = fetch(url).await?;
+            let json: Value = serde_json::from_str(&json)?;
+            let chart: Chart = serde_json::from_value(json["topartists"].clone())?;
+
+            total += chart.artists.iter().map(|a| a.playcount).sum::<u64>();
+            if chart.meta.page >= chart.meta.total_pages {
+                break;
+            }
+            page += 1;
+        }
+        Ok(total)
+    }
+
+    /// Names of all artists the user has ever listened to (more precisely, the
+    /// top 1000 of all time, which is the most last.fm returns in one request).
+    pub async fn get_known_artists(&self) -> anyhow::Result<HashSet<String>> {
+        Ok(self.get_top_artists_overall().await?.into_iter().map(|a| a.name).collect())
+    }
+
+    /// https://www.last.fm/api/show/user.getTopArtists
+    async fn get_top_artists_overall(&self) -> anyhow::Result<Vec<ChartArtist>> {
+        let url = self.url(
+            "user.gettopartists",
+            &[
+                ("limit", "1000"),
+                ("period", &Period::Overall.to_string()),
+                ("user", &self.username),
+            ],
+        )?;
+
+        let json = fetch(url).await?;
+        let json: Value = serde_json::from_str(&json)?;
+        let chart: Chart = serde_json::from_value(json["topartists"].clone())?;
+
+        Ok(chart.artists)
+    }
+
+    /// Playcounts of the artists in the user's library, keyed by lowercase
+    /// name (see `get_known_artists`). The library is cached for as long as
+    /// `UserInfo`. In offline mode, see `get_offline_library`.
+    pub async fn get_library(
+        &self,
+        pool: &SqPool,
+    ) -> anyhow::Result<HashMap<String, u64>> {
+        if *OFFLINE {
+            return get_offline_library(pool, &self.username).await;
+        }
+        if let Some(library) = get_cached_library(pool, &self.username).await? {
+            return Ok(library);
+        }
+        let library = self
+            .get_top_artists_overall()
+            .await?
+            .into_iter()
+            .map(|a| (a.name.to_lowercase(), a.playcount))
+            .collect();
+        store_library(pool, &self.username, &library).await?;
+        Ok(library)
+    }
+
+    /// Date ranges for which weekly charts are available, in ascending order
+    ///
+    /// https://www.last.fm/api/show/user.getWeeklyChartList
+    pub async fn get_chart_ranges(&self) -> anyhow::Result<Vec<ChartRange>> {
+        let url = self.url("user.getweeklychartlist", &[("user", &self.username)])?;
+
+        let json = fetch(url).await?;
+        let json: Value = serde_json::from_str(&json)?;
+        let ranges = serde_json::from_value(json["weeklychartlist"]["chart"].clone())?;
+
+        Ok(ranges)
+    }
+
     /// Unlike `get_chart_period`, this allows a custom window, as specified by
-    /// 2 timestamps. If a window is not specified, the last week (7 days) is
-    /// used.
+    /// 2 timestamps (see `ChartRange::new`).
+    ///
+    /// Last.fm only accepts windows that are aligned to the ranges returned by
+    /// `get_chart_ranges`, so the window is widened to fit these.
+    ///
+    /// Last.fm returns the entire chart at once, so pagination (`limit`, `page`)
+    /// is done locally.
     ///
     /// https://www.last.fm/api/show/user.getWeeklyArtistChart
-    pub async fn get_chart_window(&self) {
-        let _url = build_lastfm_url(
+    pub async fn get_chart_window(
+        &self,
+        from: Option<u64>,
+        to: Option<u64>,
+        limit: u32,
+        page: u32,
+    ) -> anyhow::Result<Chart> {
+        let window = ChartRange::new(from, to)?;
+        let window = snap_window(&self.get_chart_ranges().await?, window)
+            .ok_or(anyhow::anyhow!("No charts available for {}", self.username))?;
+
+        let url = self.url(
             "user.getweeklyartistchart",
-            &LASTFM_KEY,
-            &[("user", &self.username)],
-        );
+            &[
+                ("user", &self.username),
+                ("from", &window.from.to_string()),
+                ("to", &window.to.to_string()),
+            ],
+        )?;
+
+        let json = fetch(url).await?;
+        let json: Value = serde_json::from_str(&json)?;
+        let mut chart: Chart = serde_json::from_value(json["weeklyartistchart"].clone())?;
+        chart.window = Some(window);
+
+        // unlike user.getTopArtists, there is no limit param
+        Ok(chart.paginate(limit, page))
+    }
+
+    /// Like `get_chart_window`, but computed from imported scrobbles (see
+    /// `scrobbles`). Since Last.fm is not involved, the window is used as is.
+    pub async fn get_local_chart(
+        &self,
+        from: Option<u64>,
+        to: Option<u64>,
+        limit: u32,
+        page: u32,
+        pool: &SqPool,
+    ) -> anyhow::Result<Chart> {
+        let window = ChartRange::new(from, to)?;
+        let counts = get_scrobble_counts(pool, &self.username, window.from, window.to).await?;
+        Ok(Chart::from_local(counts, window).paginate(limit, page))
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::snap_window;
+    use super::ChartRange;
+    use crate::artists::LastfmError;
     use crate::charts::User;
+    use crate::tests::TestPool;
+    use crate::LASTFM_KEY;
     use crate::LASTFM_USER;
 
+    #[test]
+    fn test_snap_window() {
+        let ranges: Vec<ChartRange> = [(0, 10), (10, 20), (20, 30)]
+            .into_iter()
+            .map(|(from, to)| ChartRange { from, to })
+            .collect();
+        let snap = |from, to| snap_window(&ranges, ChartRange { from, to }).unwrap();
+
+        assert_eq!(snap(12, 18), ChartRange { from: 10, to: 20 });
+        assert_eq!(snap(5, 25), ChartRange { from: 0, to: 30 });
+        assert_eq!(snap(10, 20), ChartRange { from: 10, to: 20 });
+        // clamped
+        assert_eq!(snap(25, 100), ChartRange { from: 20, to: 30 });
+        assert!(snap_window(&[], ChartRange { from: 0, to: 1 }).is_none());
+    }
+
     #[tokio::test]
     async fn test_week() {
-        let ch = User::new(&LASTFM_USER)
+        let pool = &TestPool::new(Some(&LASTFM_KEY)).await.pool;
+        let ch = User::new(&LASTFM_USER, None, pool)
+            .await
             .unwrap()
-            .get_chart_period(crate::charts::Period::Week)
+            .get_chart_period(crate::charts::Period::Week, 10, 1)
             .await
             .unwrap();
         assert_eq!(ch.artists.first().unwrap().rank, 1);
         assert_eq!(ch.artists.last().unwrap().rank, 10);
     }
+
+    #[tokio::test]
+    async fn test_second_page() {
+        let pool = &TestPool::new(Some(&LASTFM_KEY)).await.pool;
+        let ch = User::new(&LASTFM_USER, None, pool)
+            .await
+            .unwrap()
+            .get_chart_period(crate::charts::Period::Overall, 5, 2)
+            .await
+            .unwrap();
+        assert_eq!(ch.artists.first().unwrap().rank, 6);
+        assert_eq!(ch.artists.last().unwrap().rank, 10);
+        assert_eq!(ch.meta.page, 2);
+    }
+
+    #[tokio::test]
+    async fn test_period_plays() {
+        let pool = &TestPool::new(Some(&LASTFM_KEY)).await.pool;
+        let user = User::new(&LASTFM_USER, None, pool).await.unwrap();
+        let week = user
+            .get_period_plays(crate::charts::Period::Week)
+            .await
+            .unwrap();
+        assert!(week <= user.info.playcount);
+    }
+
+    #[tokio::test]
+    async fn user_not_found() {
+        let pool = &TestPool::new(Some(&LASTFM_KEY)).await.pool;
+        let user = User::new("this user does not exist", None, pool).await;
+        assert!(matches!(user, Err(LastfmError::UserNotFound(_))));
+
+        // cached
+        let user = User::new(&LASTFM_USER, None, pool).await.unwrap();
+        let cached = User::new(&LASTFM_USER.to_uppercase(), None, pool).await.unwrap();
+        assert_eq!(user.username(), cached.username());
+    }
 }
@@ -0,0 +1,312 @@
+//! Module for comparing the charts of two users, i.e. taste compatibility.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use maud::html;
+use maud::Markup;
+use serde_json::json;
+use serde_json::Value;
+use strum::IntoEnumIterator;
+use urlencoding::encode;
+
+use crate::accounts::Account;
+use crate::artists::Artist;
+use crate::charts::ChartArtist;
+use crate::charts::Period;
+use crate::charts::User;
+use crate::html;
+use crate::SqPool;
+
+/// Number of artists fetched per user
+const CHART_SIZE: u32 = 50;
+
+/// Number of recommendations shown per user
+const RECS_SIZE: usize = 10;
+
+#[derive(Debug)]
+pub struct Overlap {
+    /// In order of user A's chart
+    pub shared: Vec<String>,
+
+    /// Spearman's rank correlation of shared artists; `None` if fewer than 2
+    /// artists are shared
+    pub rank_correlation: Option<f64>,
+
+    /// Sum of minimum shares over sum of maximum shares, where a share is an
+    /// artist's fraction of the user's (charted) plays. Playcounts are
+    /// normalised because one user may simply scrobble more than the other.
+    pub weighted_jaccard: f64,
+
+    /// Like (unweighted) Jaccard, except that an artist that is not shared
+    /// counts as a partial match, according to its highest similarity to any
+    /// artist in the other chart
+    pub similarity_overlap: f64,
+}
+
+/// `sim` should return a similarity between 0 and 100 (0 if unknown)
+fn overlap(
+    a: &[ChartArtist],
+    b: &[ChartArtist],
+    sim: impl Fn(&str, &str) -> i64,
+) -> Overlap {
+    let names_a: HashSet<&str> = a.iter().map(|x| x.name.as_str()).collect();
+    let names_b: HashSet<&str> = b.iter().map(|x| x.name.as_str()).collect();
+
+    let shared: Vec<String> = a
+        .iter()
+        .filter(|x| names_b.contains(x.name.as_str()))
+        .map(|x| x.name.clone())
+        .collect();
+
+    // shared artists, re-ranked within each chart
+    let rank_correlation = match shared.len() {
+        0 | 1 => None,
+        n => {
+            let ranks_b: HashMap<&str, usize> = b
+                .iter()
+                .filter(|x| names_a.contains(x.name.as_str()))
+                .enumerate()
+                .map(|(i, x)| (x.name.as_str(), i))
+                .collect();
+            let d2: f64 = shared
+                .iter()
+                .enumerate()
+                .map(|(i, x)| (i as f64 - ranks_b[x.as_str()] as f64).powi(2))
+                .sum();
+            let n = n as f64;
+            Some(1.0 - 6.0 * d2 / (n * (n * n - 1.0)))
+        }
+    };
+
+    let shares = |chart: &[ChartArtist]| -> HashMap<String, f64> {
+        let total: u64 = chart.iter().map(|x| x.playcount).sum();
+        chart
+            .iter()
+            .map(|x| (x.name.clone(), x.playcount as f64 / total.max(1) as f64))
+            .collect()
+    };
+    let (shares_a, shares_b) = (shares(a), shares(b));
+    let union: HashSet<&str> = names_a.union(&names_b).copied().collect();
+    let (min, max) = union.iter().fold((0.0, 0.0), |(min, max), x| {
+        let sa = shares_a.get(*x).copied().unwrap_or(0.0);
+        let sb = shares_b.get(*x).copied().unwrap_or(0.0);
+        (min + sa.min(sb), max + sa.max(sb))
+    });
+    let weighted_jaccard = if max > 0.0 { min / max } else { 0.0 };
+
+    let matches: f64 = union
+        .iter()
+        .map(|x| match (names_a.contains(x), names_b.contains(x)) {
+            (true, true) => 1.0,
+            (true, false) => closeness(x, b, &sim),
+            _ => closeness(x, a, &sim),
+        })
+        .sum();
+    let similarity_overlap = match union.len() {
+        0 => 0.0,
+        n => matches / n as f64,
+    };
+
+    Overlap {
+        shared,
+        rank_correlation,
+        weighted_jaccard,
+        similarity_overlap,
+    }
+}
+
+/// Highest similarity of `artist` to any artist in `chart`, between 0 and 1
+fn closeness(
+    artist: &str,
+    chart: &[ChartArtist],
+    sim: &impl Fn(&str, &str) -> i64,
+) -> f64 {
+    chart
+        .iter()
+        .map(|x| sim(artist, &x.name))
+        .max()
+        .unwrap_or(0) as f64
+        / 100.0
+}
+
+pub struct Comparison {
+    users: (String, String),
+    period: Period,
+    overlap: Overlap,
+
+    /// Artists from A's chart for B, and vice versa, in descending closeness
+    /// to the recipient's chart
+    recs: (Vec<String>, Vec<String>),
+}
+
+impl Comparison {
+    /// Similarities are only taken from `artist_pairs`, i.e. no requests are
+    /// made for artists that are not yet cached.
+    pub async fn new(
+        user_a: &str,
+        user_b: &str,
+        period: Period,
+        account: Option<&Account>,
+        pool: &SqPool,
+    ) -> anyhow::Result<Self> {
+        let user_a = User::new(user_a, account, pool).await?;
+        let user_b = User::new(user_b, account, pool).await?;
+        let a = user_a.get_chart_period(period, CHART_SIZE, 1).await?.artists;
+        let b = user_b.get_chart_period(period, CHART_SIZE, 1).await?.artists;
+
+        let mut sims: HashMap<(String, String), i64> = HashMap::new();
+        for artist in a.iter().chain(b.iter()) {
+            if let Some(pairs) = Artist::new(&artist.name).get_artist_pairs(pool).await? {
+                for p in pairs {
+                    sims.insert((p.parent.clone(), p.child.clone()), p.similarity);
+                    sims.insert((p.child, p.parent), p.similarity);
+                }
+            }
+        }
+        let sim = |x: &str, y: &str| {
+            sims.get(&(x.to_string(), y.to_string()))
+                .copied()
+                .unwrap_or(0)
+        };
+
+        let overlap = overlap(&a, &b, sim);
+
+        // artists the recipient has never listened to, closest to their taste
+        let recommend = |from: &[ChartArtist], to: &[ChartArtist], known: &HashSet<String>| {
+            let mut recs: Vec<(&str, f64)> = from
+                .iter()
+                .filter(|x| !known.contains(&x.name))
+                .map(|x| (x.name.as_str(), closeness(&x.name, to, &sim)))
+                .collect();
+            // stable sort preserves chart order for ties
+            recs.sort_by(|x, y| y.1.total_cmp(&x.1));
+            recs.into_iter()
+                .take(RECS_SIZE)
+                .map(|x| x.0.to_string())
+                .collect::<Vec<String>>()
+        };
+        let recs = (
+            recommend(&a, &b, &user_b.get_known_artists().await?),
+            recommend(&b, &a, &user_a.get_known_artists().await?),
+        );
+
+        Ok(Self {
+            users: (user_a.username().to_string(), user_b.username().to_string()),
+            period,
+            overlap,
+            recs,
+        })
+    }
+
+    /// Used by the JSON API. Each entry of `recommendations` lists artists from
+    /// the other user's chart.
+    pub fn as_json(&self) -> Value {
+        let (a, b) = &self.users;
+        json!({
+            "users": [a, b],
+            "period": self.period.to_string(),
+            "shared": self.overlap.shared,
+            "rank_correlation": self.overlap.rank_correlation,
+            "weighted_jaccard": self.overlap.weighted_jaccard,
+            "similarity_overlap": self.overlap.similarity_overlap,
+            "recommendations": [
+                { "user": b, "artists": self.recs.0 },
+                { "user": a, "artists": self.recs.1 },
+            ],
+        })
+    }
+
+    pub fn as_html(&self) -> Markup {
+        let (a, b) = (&self.users.0, &self.users.1);
+
+        let periods = html! {
+            @for p in Period::iter() {
+                @if p == self.period {
+                    b { (p) }
+                } @else {
+                    (html::link(&format!("/compare/{a}/{b}/{p}"), &p.to_string()))
+                }
+                " "
+            }
+        };
+
+        let artist_link = |name: &str| html::link(&format!("/artists/{}", encode(name)), name);
+        let pct = |x: f64| format!("{:.0}%", x * 100.0);
+
+        html! {
+            (html::header(&format!("{a} vs {b}")))
+            (periods)
+            ul {
+                li { "Shared artists: " (self.overlap.shared.len()) }
+                li {
+                    "Rank correlation: "
+                    (self.overlap.rank_correlation.map(|r| format!("{r:.2}")).unwrap_or("-".to_string()))
+                }
+                li { "Weighted overlap: " (pct(self.overlap.weighted_jaccard)) }
+                li { "Similarity-aware overlap: " (pct(self.overlap.similarity_overlap)) }
+            }
+
+            h3 { "Shared" }
+            ul {
+                @for x in self.overlap.shared.iter() {
+                    li { (artist_link(x)) }
+                }
+            }
+
+            @for (from, to, recs) in [(a, b, &self.recs.0), (b, a, &self.recs.1)] {
+                h3 { "From " (from) " for " (to) }
+                ol {
+                    @for x in recs.iter() {
+                        li { (artist_link(x)) }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::overlap;
+    use crate::charts::ChartArtist;
+
+    fn chart(artists: &[(&str, u64)]) -> Vec<ChartArtist> {
+        artists
+            .iter()
+            .enumerate()
+            .map(|(i, (name, playcount))| ChartArtist {
+                name: name.to_string(),
+                playcount: *playcount,
+                rank: i as u64 + 1,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn identical() {
+        let a = chart(&[("A", 3), ("B", 2), ("C", 1)]);
+        let o = overlap(&a, &a, |_, _| 0);
+        assert_eq!(o.shared, ["A", "B", "C"]);
+        assert_eq!(o.rank_correlation, Some(1.0));
+        assert_eq!(o.weighted_jaccard, 1.0);
+        assert_eq!(o.similarity_overlap, 1.0);
+    }
+
+    #[test]
+    fn partial() {
+        let a = chart(&[("A", 10), ("B", 10), ("X", 20)]);
+        let b = chart(&[("B", 10), ("A", 10), ("Y", 20)]);
+        let sim = |x: &str, y: &str| match (x, y) {
+            ("X", "Y") | ("Y", "X") => 50,
+            _ => 0,
+        };
+        let o = overlap(&a, &b, sim);
+        assert_eq!(o.shared, ["A", "B"]);
+        assert_eq!(o.rank_correlation, Some(-1.0));
+        // min: 0.25 + 0.25, max: 0.25 + 0.25 + 0.5 + 0.5
+        assert_eq!(o.weighted_jaccard, 1.0 / 3.0);
+        // (1 + 1 + 0.5 + 0.5) / 4
+        assert_eq!(o.similarity_overlap, 0.75);
+    }
+}
@@ -0,0 +1,354 @@
+//! Module for loading settings. Values are layered as follows (later layers
+//! take precedence):
+//!
+//! 1. defaults (see `Default` impls below),
+//! 2. a TOML file (`lasttree.toml`, or whichever file is passed with
+//!    `--config`; see `lasttree.example.toml`),
+//! 3. environment variables (listed in `Config::apply_env`),
+//! 4. command-line arguments (`serve` and `--db` only).
+//!
+//! The final `Config` is validated once at startup, made available globally
+//! via `config()`, and injected into the server as app data.
+
+use std::env;
+use std::fmt::Display;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use reqwest::Url;
+use serde::Deserialize;
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// File loaded if no path is given explicitly
+pub const DEFAULT_PATH: &str = "lasttree.toml";
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub lastfm: LastfmConfig,
+    pub tree: TreeConfig,
+    pub cache: CacheConfig,
+    pub player: PlayerConfig,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind: String,
+    pub port: u16,
+    /// Shown in page titles and on the home page
+    pub app_name: String,
+    /// Enable user accounts (see `accounts`). If disabled, a single API key
+    /// is shared by everyone.
+    pub accounts: bool,
+    /// Used to encrypt stored API keys (see `secret`). If unset, keys are
+    /// stored in plaintext.
+    pub secret: Option<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: "127.0.0.1".to_string(),
+            port: 3838,
+            app_name: "Last".to_string(),
+            accounts: false,
+            secret: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub url: String,
+    /// Back up the db file before applying migrations
+    pub backup: bool,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            url: "sqlite://lasttree.db".to_string(),
+            backup: false,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LastfmConfig {
+    pub base_url: String,
+    /// Used for all requests other than artist.getSimilar (which uses the key
+    /// stored in the db). Optional if a key is stored (see `lastfm_key`).
+    pub key: Option<String>,
+    /// User shown at `/charts/`
+    pub default_user: Option<String>,
+    /// See `Artist::with_offline`
+    pub offline: bool,
+    /// Required to authenticate as a Last.fm user (see `auth`)
+    pub shared_secret: Option<String>,
+}
+
+impl Default for LastfmConfig {
+    fn default() -> Self {
+        Self {
+            base_url: "http://ws.audioscrobbler.com/2.0/?format=json".to_string(),
+            key: None,
+            default_user: None,
+            offline: false,
+            shared_secret: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TreeConfig {
+    /// Minimum similarity (0 to 1) for a child to be added
+    pub threshold: f64,
+    pub depth: u8,
+}
+
+impl Default for TreeConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 0.7,
+            depth: 2,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// User info (scrobble count etc) is refetched after this many days
+    pub user_info_days: u32,
+    /// Similar artists are refetched after this many days. If unset, they are
+    /// cached forever.
+    pub similar_artists_days: Option<u32>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            user_info_days: 1,
+            similar_artists_days: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlayerConfig {
+    /// Path to `yt-dlp` (or compatible). If unset, it is looked up in `$PATH`.
+    pub ytdlp_path: Option<String>,
+    /// Number of YouTube results to search for a music video
+    pub search_results: usize,
+    pub autoplay: bool,
+}
+
+impl Default for PlayerConfig {
+    fn default() -> Self {
+        Self {
+            ytdlp_path: None,
+            search_results: 5,
+            autoplay: true,
+        }
+    }
+}
+
+/// `$name`, if set and non-empty
+fn env_var(name: &str) -> Option<String> { env::var(name).ok().filter(|v| !v.is_empty()) }
+
+/// Parse `$name` into `target`, if set
+fn env_override<T>(
+    name: &str,
+    target: &mut T,
+) -> anyhow::Result<()>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Some(val) = env_var(name) {
+        *target = val
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid value for ${name} ({val}): {e}"))?;
+    }
+    Ok(())
+}
+
+impl Config {
+    /// Load from `path`, or from `DEFAULT_PATH` if it exists, then apply
+    /// environment variables. The result is not validated.
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Self> {
+        let path = match path {
+            Some(p) => Some(p),
+            None => Some(Path::new(DEFAULT_PATH)).filter(|p| p.exists()),
+        };
+
+        let mut config = match path {
+            Some(path) => {
+                let text = std::fs::read_to_string(path)
+                    .map_err(|e| anyhow::anyhow!("Could not read {}: {e}", path.display()))?;
+                toml::from_str(&text)
+                    .map_err(|e| anyhow::anyhow!("Could not parse {}: {e}", path.display()))?
+            }
+            None => Config::default(),
+        };
+
+        config.apply_env()?;
+        Ok(config)
+    }
+
+    /// Environment variables recognised (all optional):
+    ///
+    /// - `$LASTTREE_BIND`, `$LASTTREE_PORT`, `$LASTTREE_SECRET`
+    /// - `$DATABASE_URL`
+    /// - `$LASTFM_URL`, `$LASTFM_KEY`, `$LASTFM_SECRET`, `$LASTFM_USER`,
+    ///   `$LASTTREE_OFFLINE`
+    /// - `$LASTTREE_YTDLP`
+    pub fn apply_env(&mut self) -> anyhow::Result<()> {
+        env_override("LASTTREE_BIND", &mut self.server.bind)?;
+        env_override("LASTTREE_PORT", &mut self.server.port)?;
+        env_override("DATABASE_URL", &mut self.database.url)?;
+        env_override("LASTFM_URL", &mut self.lastfm.base_url)?;
+
+        if let Some(secret) = env_var("LASTTREE_SECRET") {
+            self.server.secret = Some(secret);
+        }
+        if let Some(key) = env_var("LASTFM_KEY") {
+            self.lastfm.key = Some(key);
+        }
+        if let Some(secret) = env_var("LASTFM_SECRET") {
+            self.lastfm.shared_secret = Some(secret);
+        }
+        if let Some(user) = env_var("LASTFM_USER") {
+            self.lastfm.default_user = Some(user);
+        }
+        // lenient for backwards compatibility: anything but 0/false enables it
+        if let Some(val) = env_var("LASTTREE_OFFLINE") {
+            self.lastfm.offline = !matches!(val.as_str(), "0" | "false");
+        }
+        if let Some(path) = env_var("LASTTREE_YTDLP") {
+            self.player.ytdlp_path = Some(path);
+        }
+
+        Ok(())
+    }
+
+    /// Check for values that would only fail later (usually at the first
+    /// request). All problems are reported at once.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut problems = vec![];
+
+        if self.server.secret.as_ref().is_some_and(|s| s.len() < 16) {
+            problems.push("server.secret must be at least 16 characters long".to_string());
+        }
+
+        if !self.database.url.starts_with("sqlite:") {
+            problems.push(format!(
+                "database.url must be a sqlite url (e.g. sqlite://lasttree.db), got {}",
+                self.database.url
+            ));
+        }
+
+        if let Err(e) = Url::parse(&self.lastfm.base_url) {
+            problems.push(format!("lastfm.base_url is not a valid url: {e}"));
+        }
+
+        if !(0.0..=1.0).contains(&self.tree.threshold) {
+            problems.push(format!(
+                "tree.threshold must be between 0 and 1, got {}",
+                self.tree.threshold
+            ));
+        }
+
+        if self.tree.depth == 0 {
+            problems.push("tree.depth must be at least 1".to_string());
+        }
+
+        if self.cache.user_info_days == 0 || self.cache.similar_artists_days == Some(0) {
+            problems.push("cache durations must be at least 1 day".to_string());
+        }
+
+        if self.player.search_results == 0 {
+            problems.push("player.search_results must be at least 1".to_string());
+        }
+
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(anyhow::anyhow!(
+                "Invalid config:\n- {}",
+                problems.join("\n- ")
+            )),
+        }
+    }
+
+    /// Validate, then make `self` available via `config()`. Must be called
+    /// before `config()` is first used; calling it again is only allowed with
+    /// an equal config (e.g. `main`, then `init_server`).
+    pub fn init(self) -> anyhow::Result<&'static Config> {
+        self.validate()?;
+        if let Some(config) = CONFIG.get() {
+            return match *config == self {
+                true => Ok(config),
+                false => Err(anyhow::anyhow!("A different config is already in use")),
+            };
+        }
+        CONFIG
+            .set(self)
+            .map_err(|_| anyhow::anyhow!("Config already initialised"))?;
+        Ok(config())
+    }
+}
+
+/// The global config. If `Config::init` was never called (e.g. in tests), it is
+/// loaded (without validation) from `DEFAULT_PATH` and the environment.
+pub fn config() -> &'static Config {
+    CONFIG.get_or_init(|| Config::load(None).expect("Could not load config"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+
+    #[test]
+    fn parse_and_validate() {
+        let config: Config = toml::from_str(
+            r#"
+            [server]
+            port = 8080
+            secret = "short"
+
+            [lastfm]
+            key = "abc"
+
+            [tree]
+            threshold = 1.5
+            depth = 0
+            "#,
+        )
+        .unwrap();
+
+        // unspecified values fall back to defaults
+        assert_eq!(config.server.port, 8080);
+        assert_eq!(config.server.bind, "127.0.0.1");
+        assert_eq!(config.cache.user_info_days, 1);
+
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("tree.threshold"));
+        assert!(err.contains("tree.depth"));
+        assert!(err.contains("server.secret"));
+        assert!(!err.contains("API key"));
+
+        // the key can also be stored via `/settings`
+        assert!(Config::default().validate().is_ok());
+
+        assert!(toml::from_str::<Config>("[server]\nprot = 1").is_err());
+    }
+}
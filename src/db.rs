@@ -1,24 +1,72 @@
+use std::collections::HashMap;
 use std::str::FromStr;
 
 use serde_json::json;
+use sqlx::migrate::Migrate;
+use sqlx::migrate::MigrateError;
 use sqlx::sqlite::SqliteConnectOptions;
 use sqlx::sqlite::SqlitePoolOptions;
 use sqlx::Pool;
 use sqlx::Sqlite;
 
+use crate::accounts::Account;
+use crate::accounts::ArtistList;
 use crate::artists::Artist;
+use crate::artists::LastfmError;
+use crate::auth::LastfmSession;
+use crate::charts::Chart;
+use crate::charts::Period;
+use crate::charts::UserInfo;
+use crate::config::config;
+use crate::scrobbles::Scrobble;
+use crate::secret;
 use crate::utils::build_lastfm_url;
 
 pub type SqPool = Pool<Sqlite>;
 
-// TODO: on "error: migration ... was previously applied but is missing in the
-// resolved migrations" (on deleting an .sql file which was migrated) -- may be
-// possible to avoid nuking the db by "delet[ing] the version row from the
-// _sqlx_migration table"
-//
+/// Apply all embedded migrations (in `migrations/`) that have not yet been
+/// applied. If `backup` is true and there is anything to apply, the db file is
+/// first copied to `<file>.<timestamp>.bak`.
+///
+/// A migration that was applied, but whose .sql file has since been deleted,
+/// causes sqlx to refuse to run; in that case, an error explaining how to
+/// recover (without nuking the db) is returned.
 // https://old.reddit.com/r/rust/comments/12z6n77/resolving_previously_applied_but_missing_error_in/l0qlcz6/
+pub async fn run_migrations(
+    pool: &SqPool,
+    db_url: &str,
+    backup: bool,
+) -> anyhow::Result<()> {
+    let migrator = sqlx::migrate!();
 
-// TODO: seed db with the 25 most popular artists of a given genre
+    if backup {
+        let mut conn = pool.acquire().await?;
+        conn.ensure_migrations_table().await?;
+        let applied = conn.list_applied_migrations().await?;
+        let pending = migrator
+            .iter()
+            .any(|m| !applied.iter().any(|a| a.version == m.version));
+
+        let path = SqliteConnectOptions::from_str(db_url)?
+            .get_filename()
+            .to_path_buf();
+        if pending && path.exists() {
+            let stamp = chrono::Utc::now().format("%Y%m%d%H%M%S");
+            let dest = format!("{}.{stamp}.bak", path.display());
+            std::fs::copy(&path, &dest)?;
+            println!("Backed up {} to {dest}", path.display());
+        }
+    }
+
+    match migrator.run(pool).await {
+        Err(MigrateError::VersionMissing(v)) => Err(anyhow::anyhow!(
+            "Migration {v} was previously applied to {db_url}, but is missing from migrations/. \
+            Either restore the .sql file, or, if the migration is no longer needed, remove it \
+            from the db with: DELETE FROM _sqlx_migrations WHERE version = {v};"
+        )),
+        r => Ok(r?),
+    }
+}
 
 pub fn init_db(db_url: &str) -> sqlx::Result<SqPool> {
     // to enable `sqlx migrate run`, ensure sqlx-cli is installed with the
@@ -128,12 +176,17 @@ impl Artist {
     //}}}
 
     // similars {{{
-    /// Return pairs in descending similarity
+    /// Return pairs in descending similarity. Pairs older than
+    /// `cache.similar_artists_days` (if set) are ignored.
     pub async fn get_artist_pairs(
         &self,
         pool: &SqPool,
     ) -> sqlx::Result<Option<Vec<ArtistPair>>> {
         let name = self.canonical_name(pool).await?;
+        let max_age = config()
+            .cache
+            .similar_artists_days
+            .map(|d| format!("-{d} days"));
 
         let mut pairs: Vec<ArtistPair> = sqlx::query!(
             r#"
@@ -144,8 +197,10 @@ impl Artist {
             FROM artist_pairs
             -- WHERE parent = $1 COLLATE NOCASE
             WHERE parent = $1
+            AND ($2 IS NULL OR date_added > date('now', $2))
         "#,
             name,
+            max_age,
         )
         // note: the `Vec` returned may be empty
         .fetch_all(pool)
@@ -167,7 +222,8 @@ impl Artist {
         })
     }
 
-    /// `parent` and `child` must both be canonical names.
+    /// `parent` and `child` must both be canonical names. Existing pairs are
+    /// replaced (and thus refreshed).
     ///
     /// Because sqlite does not support the `NUMERIC` type, `similarity` is cast
     /// to integer before insertion into db.
@@ -181,7 +237,7 @@ impl Artist {
         let sim_int = (similarity * 100.0) as u32;
         sqlx::query!(
             r#"
-            INSERT OR IGNORE INTO artist_pairs
+            INSERT OR REPLACE INTO artist_pairs
             (
                 parent, -- parent_lower,
                 child, -- child_lower,
@@ -255,6 +311,351 @@ impl Artist {
     }
 }
 
+/// Tags that co-occur with `tag` among cached artists, along with the number of
+/// artists tagged with `tag`. Only local data is used.
+// https://www.sqlite.org/json1.html#jeach
+pub async fn get_tag_cooccurrence(
+    pool: &SqPool,
+    tag: &str,
+    limit: u32,
+) -> sqlx::Result<(i64, Vec<(String, i64)>)> {
+    let tag = tag.to_lowercase();
+
+    let total = sqlx::query!(
+        r#"
+        SELECT COUNT(DISTINCT artists.name) as "total!: i64"
+        FROM artists, json_each(artists.tags)
+        WHERE lower(json_each.value) = $1
+        "#,
+        tag
+    )
+    .fetch_one(pool)
+    .await?
+    .total;
+
+    let rows = sqlx::query!(
+        r#"
+        WITH tagged AS (
+            SELECT artists.name
+            FROM artists, json_each(artists.tags)
+            WHERE lower(json_each.value) = $1
+        )
+        SELECT
+            lower(json_each.value) as "tag!: String",
+            COUNT(DISTINCT artists.name) as "count!: i64"
+        FROM artists, json_each(artists.tags)
+        WHERE artists.name IN (SELECT name FROM tagged)
+            AND lower(json_each.value) != $1
+        GROUP BY lower(json_each.value)
+        ORDER BY 2 DESC
+        LIMIT $2
+        "#,
+        tag,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok((total, rows.into_iter().map(|r| (r.tag, r.count)).collect()))
+}
+
+impl UserInfo {
+    /// Returns `None` if the user is not cached, or was cached more than
+    /// `cache.user_info_days` ago (by default, before today)
+    pub async fn get_cached(
+        pool: &SqPool,
+        username: &str,
+    ) -> sqlx::Result<Option<Self>> {
+        let lower = username.to_lowercase();
+        let max_age = format!("-{} days", config().cache.user_info_days);
+        let row = sqlx::query!(
+            r#"
+            SELECT name, realname, country, registered, playcount
+            FROM users
+            WHERE name_lower = $1
+            AND date_added > date('now', $2)
+            "#,
+            lower,
+            max_age
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(row.map(|r| UserInfo {
+            name: r.name,
+            realname: r.realname,
+            country: r.country,
+            registered: r.registered as u64,
+            playcount: r.playcount as u64,
+        }))
+    }
+
+    /// Add to db, replacing stale info
+    pub async fn store(
+        &self,
+        pool: &SqPool,
+    ) -> sqlx::Result<()> {
+        let lower = self.name.to_lowercase();
+        // sqlite has no unsigned integers
+        let registered = self.registered as i64;
+        let playcount = self.playcount as i64;
+        sqlx::query!(
+            r#"
+            INSERT OR REPLACE INTO users
+            (name, name_lower, realname, country, registered, playcount, date_added)
+            VALUES ($1, $2, $3, $4, $5, $6, date())
+            "#,
+            self.name,
+            lower,
+            self.realname,
+            self.country,
+            registered,
+            playcount,
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+}
+
+/// Cached library of `user` (see `User::get_library`), with the same expiry as
+/// `UserInfo`
+pub async fn get_cached_library(
+    pool: &SqPool,
+    user: &str,
+) -> sqlx::Result<Option<HashMap<String, u64>>> {
+    let user = user.to_lowercase();
+    let max_age = format!("-{} days", config().cache.user_info_days);
+    let row = sqlx::query!(
+        r#"
+        SELECT artists as "artists!: serde_json::Value"
+        FROM libraries
+        WHERE user = $1
+        AND date_added > date('now', $2)
+        "#,
+        user,
+        max_age
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.and_then(|r| serde_json::from_value(r.artists).ok()))
+}
+
+/// Replaces any previously cached library
+pub async fn store_library(
+    pool: &SqPool,
+    user: &str,
+    artists: &HashMap<String, u64>,
+) -> sqlx::Result<()> {
+    let user = user.to_lowercase();
+    let artists = json!(artists);
+    sqlx::query!(
+        r#"
+        INSERT OR REPLACE INTO libraries (user, artists, date_added)
+        VALUES ($1, $2, date())
+        "#,
+        user,
+        artists
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// chart snapshots {{{
+#[derive(Debug, Clone)]
+pub struct SnapshotRow {
+    pub artist: String,
+    pub rank: i64,
+    pub playcount: i64,
+}
+
+impl Chart {
+    /// Store as today's snapshot for `username`, replacing any earlier one from
+    /// the same day. Only the first page of a chart should be stored.
+    pub async fn store_snapshot(
+        &self,
+        pool: &SqPool,
+        username: &str,
+    ) -> sqlx::Result<()> {
+        let period = self.period.to_string();
+        let mut tx = pool.begin().await?;
+        sqlx::query!(
+            r#"
+            DELETE FROM chart_snapshots
+            WHERE username = $1 AND period = $2 AND date_fetched = date()
+            "#,
+            username,
+            period,
+        )
+        .execute(&mut *tx)
+        .await?;
+        for artist in self.artists.iter() {
+            let rank = artist.rank as i64;
+            let playcount = artist.playcount as i64;
+            sqlx::query!(
+                r#"
+                INSERT OR REPLACE INTO chart_snapshots
+                (username, period, date_fetched, artist, rank, playcount)
+                VALUES ($1, $2, date(), $3, $4, $5)
+                "#,
+                username,
+                period,
+                artist.name,
+                rank,
+                playcount,
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+/// Dates (`YYYY-MM-DD`) of all snapshots of a user's chart, most recent first
+pub async fn get_snapshot_dates(
+    pool: &SqPool,
+    username: &str,
+    period: Period,
+) -> sqlx::Result<Vec<String>> {
+    let period = period.to_string();
+    let rows = sqlx::query!(
+        r#"
+        SELECT DISTINCT date_fetched
+        FROM chart_snapshots
+        WHERE username = $1
+        AND period = $2
+        ORDER BY date_fetched DESC
+        "#,
+        username,
+        period,
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|r| r.date_fetched).collect())
+}
+
+/// Ascending rank
+pub async fn get_snapshot(
+    pool: &SqPool,
+    username: &str,
+    period: Period,
+    date: &str,
+) -> sqlx::Result<Vec<SnapshotRow>> {
+    let period = period.to_string();
+    let rows = sqlx::query_as!(
+        SnapshotRow,
+        r#"
+        SELECT artist, rank, playcount
+        FROM chart_snapshots
+        WHERE username = $1
+        AND period = $2
+        AND date_fetched = $3
+        ORDER BY rank
+        "#,
+        username,
+        period,
+        date,
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+//}}}
+
+// seed queue {{{
+#[derive(Debug)]
+pub struct SeedItem {
+    pub artist: String,
+    pub tag: String,
+    pub depth: i64,
+    pub max_depth: i64,
+}
+
+/// If `artist` was already queued, it is only set back to pending when
+/// reached via a shallower path, or by a job with a greater `max_depth` (so
+/// that a finished job can be deepened by running it again)
+pub async fn enqueue_seed(
+    pool: &SqPool,
+    artist: &str,
+    tag: &str,
+    depth: i64,
+    max_depth: i64,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO seed_queue
+        (artist, tag, depth, max_depth, status, date_added)
+        VALUES ($1, $2, $3, $4, 'pending', date())
+        ON CONFLICT (artist) DO UPDATE SET
+            tag = CASE WHEN excluded.depth < depth THEN excluded.tag ELSE tag END,
+            depth = MIN(depth, excluded.depth),
+            max_depth = MAX(max_depth, excluded.max_depth),
+            status = 'pending'
+        WHERE excluded.depth < depth OR excluded.max_depth > max_depth
+        "#,
+        artist,
+        tag,
+        depth,
+        max_depth,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Shallowest pending item first, i.e. breadth-first
+pub async fn next_seed(pool: &SqPool) -> sqlx::Result<Option<SeedItem>> {
+    sqlx::query_as!(
+        SeedItem,
+        r#"
+        SELECT artist, tag, depth, max_depth
+        FROM seed_queue
+        WHERE status = 'pending'
+        ORDER BY depth, rowid
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn set_seed_status(
+    pool: &SqPool,
+    artist: &str,
+    status: &str,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE seed_queue
+        SET status = $1
+        WHERE artist = $2
+        "#,
+        status,
+        artist,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Number of queued artists per status
+pub async fn get_seed_progress(pool: &SqPool) -> sqlx::Result<Vec<(String, i64)>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT status, COUNT(*) as "count!: i64"
+        FROM seed_queue
+        GROUP BY status
+        ORDER BY status
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|r| (r.status, r.count)).collect())
+}
+//}}}
+
 pub async fn get_random_artist(pool: &SqPool) -> sqlx::Result<Option<String>> {
     let row = sqlx::query!(
         r#"
@@ -268,10 +669,436 @@ pub async fn get_random_artist(pool: &SqPool) -> sqlx::Result<Option<String>> {
     Ok(row.map(|r| r.name))
 }
 
+// accounts {{{
+impl Account {
+    /// Returns the id of the new account. Fails if `name` is taken.
+    pub async fn create(
+        pool: &SqPool,
+        name: &str,
+        password_hash: &str,
+    ) -> sqlx::Result<i64> {
+        let id = sqlx::query!(
+            r#"
+            INSERT INTO accounts (name, password_hash, date_added)
+            VALUES ($1, $2, date())
+            "#,
+            name,
+            password_hash
+        )
+        .execute(pool)
+        .await?
+        .last_insert_rowid();
+        Ok(id)
+    }
+
+    /// `(id, password_hash)`
+    pub async fn get_password_hash(
+        pool: &SqPool,
+        name: &str,
+    ) -> sqlx::Result<Option<(i64, String)>> {
+        let row = sqlx::query!(
+            r#"
+            SELECT id as "id!: i64", password_hash
+            FROM accounts
+            WHERE name = $1
+            "#,
+            name
+        )
+        .fetch_optional(pool)
+        .await?;
+        Ok(row.map(|r| (r.id, r.password_hash)))
+    }
+
+    /// The account's API key is decrypted
+    pub async fn from_session(
+        pool: &SqPool,
+        token: &str,
+    ) -> anyhow::Result<Option<Self>> {
+        let row = sqlx::query!(
+            r#"
+            SELECT accounts.id as "id!: i64", name, lastfm_user, api_key
+            FROM sessions
+            JOIN accounts ON accounts.id = sessions.account_id
+            WHERE token = $1
+            "#,
+            token
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        let Some(r) = row else {
+            return Ok(None);
+        };
+        Ok(Some(Account {
+            id: r.id,
+            name: r.name,
+            lastfm_user: r.lastfm_user,
+            api_key: r.api_key.map(|k| secret::decrypt(&k)).transpose()?,
+        }))
+    }
+
+    /// Returns a new session token
+    pub async fn create_session(
+        pool: &SqPool,
+        account_id: i64,
+    ) -> sqlx::Result<String> {
+        let token = uuid::Uuid::new_v4().simple().to_string();
+        sqlx::query!(
+            r#"
+            INSERT INTO sessions (token, account_id, date_added)
+            VALUES ($1, $2, date())
+            "#,
+            token,
+            account_id
+        )
+        .execute(pool)
+        .await?;
+        Ok(token)
+    }
+
+    pub async fn delete_session(
+        pool: &SqPool,
+        token: &str,
+    ) -> sqlx::Result<()> {
+        sqlx::query!("DELETE FROM sessions WHERE token = $1", token)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Empty strings are stored as `NULL`
+    pub async fn set_lastfm_user(
+        &self,
+        pool: &SqPool,
+        lastfm_user: &str,
+    ) -> sqlx::Result<()> {
+        let lastfm_user = Some(lastfm_user.trim()).filter(|s| !s.is_empty());
+        sqlx::query!(
+            "UPDATE accounts SET lastfm_user = $1 WHERE id = $2",
+            lastfm_user,
+            self.id
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// `None` removes the key. The key is encrypted, but not validated (see
+    /// `validate_api_key`).
+    pub async fn set_api_key(
+        &self,
+        pool: &SqPool,
+        api_key: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let api_key = api_key.map(|k| secret::encrypt(k.trim())).transpose()?;
+        sqlx::query!(
+            "UPDATE accounts SET api_key = $1 WHERE id = $2",
+            api_key,
+            self.id
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn add_artist(
+        &self,
+        pool: &SqPool,
+        list: ArtistList,
+        artist: &str,
+    ) -> sqlx::Result<()> {
+        let list = list.to_string();
+        sqlx::query!(
+            r#"
+            INSERT OR IGNORE INTO account_artists (account_id, artist, list, date_added)
+            VALUES ($1, $2, $3, date())
+            "#,
+            self.id,
+            artist,
+            list
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn remove_artist(
+        &self,
+        pool: &SqPool,
+        list: ArtistList,
+        artist: &str,
+    ) -> sqlx::Result<()> {
+        let list = list.to_string();
+        sqlx::query!(
+            r#"
+            DELETE FROM account_artists
+            WHERE account_id = $1 AND artist = $2 AND list = $3
+            "#,
+            self.id,
+            artist,
+            list
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Most recently added first
+    pub async fn get_artists(
+        &self,
+        pool: &SqPool,
+        list: ArtistList,
+    ) -> sqlx::Result<Vec<String>> {
+        let list = list.to_string();
+        let rows = sqlx::query!(
+            r#"
+            SELECT artist
+            FROM account_artists
+            WHERE account_id = $1 AND list = $2
+            ORDER BY date_added DESC, rowid DESC
+            "#,
+            self.id,
+            list
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(rows.into_iter().map(|r| r.artist).collect())
+    }
+
+    pub async fn add_history(
+        &self,
+        pool: &SqPool,
+        artist: &str,
+    ) -> sqlx::Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO history (account_id, artist, date_visited)
+            VALUES ($1, $2, datetime())
+            "#,
+            self.id,
+            artist
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// `(artist, date_visited)`, most recent first
+    pub async fn get_history(
+        &self,
+        pool: &SqPool,
+        limit: u32,
+    ) -> sqlx::Result<Vec<(String, String)>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT artist, date_visited
+            FROM history
+            WHERE account_id = $1
+            ORDER BY date_visited DESC, rowid DESC
+            LIMIT $2
+            "#,
+            self.id,
+            limit
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(rows.into_iter().map(|r| (r.artist, r.date_visited)).collect())
+    }
+}
+//}}}
+
+// scrobbles {{{
+/// Returns the number of new scrobbles. Duplicates (e.g. from overlapping
+/// imports) are ignored.
+pub async fn store_scrobbles(
+    pool: &SqPool,
+    user: &str,
+    scrobbles: &[Scrobble],
+) -> sqlx::Result<u64> {
+    let user = user.to_lowercase();
+    let mut tx = pool.begin().await?;
+    let mut n = 0;
+    for s in scrobbles {
+        n += sqlx::query!(
+            r#"
+            INSERT OR IGNORE INTO scrobbles (user, artist, track, album, timestamp)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+            user,
+            s.artist,
+            s.track,
+            s.album,
+            s.timestamp,
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    }
+    tx.commit().await?;
+    Ok(n)
+}
+
+/// Time of the most recent imported scrobble
+pub async fn get_latest_scrobble(
+    pool: &SqPool,
+    user: &str,
+) -> sqlx::Result<Option<i64>> {
+    let user = user.to_lowercase();
+    let row = sqlx::query!(
+        r#"SELECT MAX(timestamp) as "latest?: i64" FROM scrobbles WHERE user = $1"#,
+        user
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(row.latest)
+}
+
+/// `(weekday, hour, plays)`, where weekday 0 is Monday (UTC)
+pub async fn get_scrobble_heatmap(
+    pool: &SqPool,
+    user: &str,
+) -> sqlx::Result<Vec<(i64, i64, i64)>> {
+    let user = user.to_lowercase();
+    // %w starts on Sunday
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            (CAST(strftime('%w', timestamp, 'unixepoch') AS INTEGER) + 6) % 7 as "weekday!: i64",
+            CAST(strftime('%H', timestamp, 'unixepoch') AS INTEGER) as "hour!: i64",
+            COUNT(*) as "plays!: i64"
+        FROM scrobbles
+        WHERE user = $1
+        GROUP BY 1, 2
+        "#,
+        user
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|r| (r.weekday, r.hour, r.plays)).collect())
+}
+
+/// Days (`YYYY-MM-DD`, UTC) with at least one scrobble, oldest first
+pub async fn get_scrobble_days(
+    pool: &SqPool,
+    user: &str,
+) -> sqlx::Result<Vec<String>> {
+    let user = user.to_lowercase();
+    let rows = sqlx::query!(
+        r#"
+        SELECT DISTINCT date(timestamp, 'unixepoch') as "day!: String"
+        FROM scrobbles
+        WHERE user = $1
+        ORDER BY 1
+        "#,
+        user
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|r| r.day).collect())
+}
+
+/// `(artist, plays, first scrobbled, last scrobbled)`, most played first.
+/// Artists are compared case-insensitively.
+pub async fn get_scrobble_artists(
+    pool: &SqPool,
+    user: &str,
+) -> sqlx::Result<Vec<(String, i64, i64, i64)>> {
+    let user = user.to_lowercase();
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            MIN(artist) as "artist!: String",
+            COUNT(*) as "plays!: i64",
+            MIN(timestamp) as "first!: i64",
+            MAX(timestamp) as "last!: i64"
+        FROM scrobbles
+        WHERE user = $1
+        GROUP BY lower(artist)
+        ORDER BY 2 DESC, 1
+        "#,
+        user
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|r| (r.artist, r.plays, r.first, r.last))
+        .collect())
+}
+
+/// `(year, artist, plays)` of the `limit` most played artists of each year
+/// (UTC), in chronological order, most played first
+pub async fn get_yearly_top_artists(
+    pool: &SqPool,
+    user: &str,
+    limit: i64,
+) -> sqlx::Result<Vec<(i64, String, i64)>> {
+    let user = user.to_lowercase();
+    let rows = sqlx::query!(
+        r#"
+        SELECT year as "year!: i64", artist as "artist!: String", plays as "plays!: i64"
+        FROM (
+            SELECT
+                CAST(strftime('%Y', timestamp, 'unixepoch') AS INTEGER) as year,
+                MIN(artist) as artist,
+                COUNT(*) as plays,
+                ROW_NUMBER() OVER (
+                    PARTITION BY CAST(strftime('%Y', timestamp, 'unixepoch') AS INTEGER)
+                    ORDER BY COUNT(*) DESC, MIN(artist)
+                ) as rank
+            FROM scrobbles
+            WHERE user = $1
+            GROUP BY 1, lower(artist)
+        )
+        WHERE rank <= $2
+        ORDER BY year, rank
+        "#,
+        user,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|r| (r.year, r.artist, r.plays))
+        .collect())
+}
+
+/// Plays per artist in `[from, to)`, in descending order. Artists are
+/// compared case-insensitively.
+pub async fn get_scrobble_counts(
+    pool: &SqPool,
+    user: &str,
+    from: u64,
+    to: u64,
+) -> sqlx::Result<Vec<(String, i64)>> {
+    let user = user.to_lowercase();
+    let (from, to) = (from as i64, to as i64);
+    let rows = sqlx::query!(
+        r#"
+        SELECT MIN(artist) as "artist!: String", COUNT(*) as "plays!: i64"
+        FROM scrobbles
+        WHERE user = $1 AND timestamp >= $2 AND timestamp < $3
+        GROUP BY lower(artist)
+        ORDER BY 2 DESC, 1
+        "#,
+        user,
+        from,
+        to
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|r| (r.artist, r.plays)).collect())
+}
+//}}}
+
 // api key {{{
 // if self-hosting, a single api key is enough, and we don't need a proper
-// login/authentication procedure
-pub async fn get_api_key(pool: &SqPool) -> sqlx::Result<Option<String>> {
+// login/authentication procedure. keys are encrypted at rest if
+// `server.secret` is set (see `secret`)
+
+/// Decrypted
+pub async fn get_api_key(pool: &SqPool) -> anyhow::Result<Option<String>> {
     let row = sqlx::query!(
         r#"
         SELECT key FROM api_key
@@ -281,27 +1108,66 @@ pub async fn get_api_key(pool: &SqPool) -> sqlx::Result<Option<String>> {
     .fetch_optional(pool)
     .await?;
 
-    Ok(row.map(|r| r.key))
+    row.map(|r| secret::decrypt(&r.key)).transpose()
+}
+
+/// The key to use for requests that do not fall back to `get_api_key` by
+/// themselves: `key` (e.g. an account's own key), then `lastfm.key`, then the
+/// stored key. Either of the latter is enough to run the app.
+pub async fn lastfm_key(
+    pool: &SqPool,
+    key: Option<&str>,
+) -> Result<String, LastfmError> {
+    let key = key
+        .or(config().lastfm.key.as_deref())
+        .filter(|k| !k.trim().is_empty());
+    if let Some(key) = key {
+        return Ok(key.to_string());
+    }
+    get_api_key(pool).await?.ok_or(LastfmError::NoApiKey)
+}
+
+/// Date the key was stored
+pub async fn get_api_key_date(pool: &SqPool) -> sqlx::Result<Option<String>> {
+    let row = sqlx::query!(r#"SELECT date_added as "date_added?: String" FROM api_key LIMIT 1"#)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.and_then(|r| r.date_added))
+}
+
+/// Check that Last.fm accepts `key`. Network errors are reported separately,
+/// so that a valid key is not rejected just because Last.fm is unreachable.
+pub async fn validate_api_key(key: &str) -> anyhow::Result<()> {
+    // TODO: is there a better dummy request?
+    let url = build_lastfm_url("chart.gettoptags", key, &[("limit", "1")])?;
+    let resp = reqwest::get(url)
+        .await
+        .map_err(|e| anyhow::anyhow!("Could not reach Last.fm: {e}"))?;
+    match resp.status().as_u16() {
+        200 => Ok(()),
+        400 | 403 => anyhow::bail!("Invalid API key"),
+        code => anyhow::bail!("Could not validate API key: Last.fm returned {code}"),
+    }
 }
 
+/// Validate `key`, then store it, replacing any existing key
 pub async fn store_api_key(
     pool: &SqPool,
     key: &str,
 ) -> anyhow::Result<()> {
     let key = key.trim();
+    if key.is_empty() {
+        anyhow::bail!("No API key given");
+    }
+    validate_api_key(key).await?;
 
-    // TODO: is there a better dummy request?
-    let url = build_lastfm_url("chart.gettoptags", key, &[("limit", "1")])?;
-    (reqwest::get(url).await?.status().as_u16() == 200)
-        .then_some(())
-        .ok_or(anyhow::anyhow!("Invalid API key"))?;
-
+    let stored = secret::encrypt(key)?;
     sqlx::query!(
         r#"
-        INSERT INTO api_key
-        VALUES ($1)
+        INSERT OR REPLACE INTO api_key (id, key, date_added)
+        VALUES (1, $1, date())
         "#,
-        key
+        stored
     )
     .execute(pool)
     .await?;
@@ -309,18 +1175,226 @@ pub async fn store_api_key(
     Ok(())
 }
 
+/// Encrypt any keys (including Last.fm session keys) still stored in plaintext
+/// (e.g. from before `server.secret` was set). Returns the number of keys
+/// encrypted. Does nothing if no secret is set.
+pub async fn encrypt_stored_keys(pool: &SqPool) -> anyhow::Result<usize> {
+    if config().server.secret.is_none() {
+        return Ok(0);
+    }
+
+    let mut n = 0;
+
+    if let Some(r) = sqlx::query!("SELECT key FROM api_key LIMIT 1")
+        .fetch_optional(pool)
+        .await?
+    {
+        if !secret::is_encrypted(&r.key) {
+            let stored = secret::encrypt(&r.key)?;
+            sqlx::query!("UPDATE api_key SET key = $1", stored)
+                .execute(pool)
+                .await?;
+            n += 1;
+        }
+    }
+
+    let rows = sqlx::query!(
+        r#"
+        SELECT id, api_key as "api_key!: String"
+        FROM accounts
+        WHERE api_key IS NOT NULL
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+    for r in rows.iter().filter(|r| !secret::is_encrypted(&r.api_key)) {
+        let stored = secret::encrypt(&r.api_key)?;
+        sqlx::query!("UPDATE accounts SET api_key = $1 WHERE id = $2", stored, r.id)
+            .execute(pool)
+            .await?;
+        n += 1;
+    }
+
+    let rows = sqlx::query!("SELECT name, session_key FROM lastfm_sessions")
+        .fetch_all(pool)
+        .await?;
+    for r in rows.iter().filter(|r| !secret::is_encrypted(&r.session_key)) {
+        let stored = secret::encrypt(&r.session_key)?;
+        sqlx::query!(
+            "UPDATE lastfm_sessions SET session_key = $1 WHERE name = $2",
+            stored,
+            r.name
+        )
+        .execute(pool)
+        .await?;
+        n += 1;
+    }
+
+    Ok(n)
+}
+
 pub async fn delete_api_key(pool: &SqPool) -> sqlx::Result<()> {
     sqlx::query!("DELETE FROM api_key",).execute(pool).await?;
     Ok(())
 }
 //}}}
 
+// lastfm sessions {{{
+/// Replaces any existing session for the same Last.fm user
+pub async fn store_lastfm_session(
+    pool: &SqPool,
+    session: &LastfmSession,
+    account_id: Option<i64>,
+) -> anyhow::Result<()> {
+    let key = secret::encrypt(&session.key)?;
+    sqlx::query!(
+        r#"
+        INSERT OR REPLACE INTO lastfm_sessions (name, session_key, account_id, date_added)
+        VALUES ($1, $2, $3, date())
+        "#,
+        session.name,
+        key,
+        account_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Decrypted session key of a Last.fm user, if they have connected their
+/// account. Only sessions belonging to `account_id` (see `get_lastfm_sessions`)
+/// are returned.
+pub async fn get_lastfm_session(
+    pool: &SqPool,
+    name: &str,
+    account_id: Option<i64>,
+) -> anyhow::Result<Option<String>> {
+    let row = sqlx::query!(
+        r#"
+        SELECT session_key FROM lastfm_sessions
+        WHERE name = $1 COLLATE NOCASE AND account_id IS $2
+        "#,
+        name,
+        account_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    row.map(|r| secret::decrypt(&r.session_key)).transpose()
+}
+
+/// Last.fm users connected by an account, or (if `account_id` is `None`)
+/// without one
+pub async fn get_lastfm_sessions(
+    pool: &SqPool,
+    account_id: Option<i64>,
+) -> sqlx::Result<Vec<String>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT name FROM lastfm_sessions
+        WHERE account_id IS $1
+        ORDER BY name
+        "#,
+        account_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|r| r.name).collect())
+}
+
+/// Only sessions belonging to `account_id` (see `get_lastfm_sessions`) are
+/// deleted
+pub async fn delete_lastfm_session(
+    pool: &SqPool,
+    name: &str,
+    account_id: Option<i64>,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        "DELETE FROM lastfm_sessions WHERE name = $1 AND account_id IS $2",
+        name,
+        account_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+//}}}
+
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+
+    use crate::accounts::Account;
     use crate::artists::Artist;
+    use crate::auth::LastfmSession;
+    use crate::delete_api_key;
+    use crate::enqueue_seed;
+    use crate::get_api_key;
+    use crate::get_cached_library;
+    use crate::get_lastfm_session;
+    use crate::init_db;
+    use crate::next_seed;
+    use crate::run_migrations;
+    use crate::set_seed_status;
+    use crate::store_api_key;
+    use crate::store_lastfm_session;
+    use crate::store_library;
     use crate::tests::TestPool;
     use crate::LASTFM_KEY;
 
+    #[tokio::test]
+    async fn missing_migration() {
+        let test_pool = TestPool::new(None).await;
+        let pool = &test_pool.pool;
+        let db_url = format!("sqlite://{}", test_pool.path);
+
+        // applying twice is a no-op
+        run_migrations(pool, &db_url, false).await.unwrap();
+
+        // simulate a deleted .sql file
+        sqlx::query(
+            r#"
+            INSERT INTO _sqlx_migrations
+            (version, description, success, checksum, execution_time)
+            VALUES (1, 'deleted', TRUE, X'00', 0)
+            "#,
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+
+        let err = run_migrations(pool, &db_url, false).await.unwrap_err();
+        assert!(err.to_string().contains("_sqlx_migrations"));
+    }
+
+    #[tokio::test]
+    async fn backup_before_migrating() {
+        let path = format!("/tmp/test-{}.db", uuid::Uuid::new_v4());
+        let db_url = format!("sqlite://{path}");
+        let pool = &init_db(&db_url).unwrap();
+
+        // create the file, so that there is something to back up
+        sqlx::query("SELECT 1").execute(pool).await.unwrap();
+        run_migrations(pool, &db_url, true).await.unwrap();
+
+        let backups = || -> Vec<String> {
+            std::fs::read_dir("/tmp")
+                .unwrap()
+                .filter_map(|e| e.ok())
+                .map(|e| e.path().display().to_string())
+                .filter(|p| p.starts_with(&path) && p.ends_with(".bak"))
+                .collect()
+        };
+        assert_eq!(backups().len(), 1);
+
+        // nothing pending, so no second backup
+        run_migrations(pool, &db_url, true).await.unwrap();
+        assert_eq!(backups().len(), 1);
+
+        for p in backups().iter().chain([&path]) {
+            std::fs::remove_file(p).unwrap();
+        }
+    }
+
     #[tokio::test]
     async fn tags() {
         let pool = &TestPool::new(Some(&LASTFM_KEY)).await.pool;
@@ -336,4 +1410,68 @@ mod tests {
         a.store_tags(pool, &tags).await.unwrap();
         assert_eq!(tags, a.get_tags_db(pool).await.unwrap().unwrap());
     }
+
+    #[tokio::test]
+    async fn replace_api_key() {
+        let pool = &TestPool::new(Some(&LASTFM_KEY)).await.pool;
+
+        // previously failed due to the UNIQUE constraint
+        store_api_key(pool, &LASTFM_KEY).await.unwrap();
+        assert_eq!(get_api_key(pool).await.unwrap().as_deref(), Some(LASTFM_KEY.as_str()));
+
+        let err = store_api_key(pool, "foo").await.unwrap_err();
+        assert_eq!(err.to_string(), "Invalid API key");
+        // rejected keys do not replace the existing one
+        assert!(get_api_key(pool).await.unwrap().is_some());
+
+        delete_api_key(pool).await.unwrap();
+        assert!(get_api_key(pool).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn cached_library() {
+        let pool = &TestPool::new(None).await.pool;
+        assert!(get_cached_library(pool, "foo").await.unwrap().is_none());
+
+        let library = HashMap::from([("metallica".to_string(), 3)]);
+        store_library(pool, "Foo", &library).await.unwrap();
+        assert_eq!(get_cached_library(pool, "FOO").await.unwrap(), Some(library));
+    }
+
+    #[tokio::test]
+    async fn session_owner() {
+        let pool = &TestPool::new(None).await.pool;
+        let id = Account::create(pool, "alice", "hash").await.unwrap();
+        let session = LastfmSession {
+            name: "foo".to_string(),
+            key: "sk".to_string(),
+        };
+        store_lastfm_session(pool, &session, Some(id)).await.unwrap();
+
+        let get = |account_id| get_lastfm_session(pool, "FOO", account_id);
+        assert_eq!(get(Some(id)).await.unwrap().as_deref(), Some("sk"));
+        assert!(get(None).await.unwrap().is_none());
+        assert!(get(Some(id + 1)).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn requeue_seed() {
+        let pool = &TestPool::new(None).await.pool;
+        enqueue_seed(pool, "foo", "rock", 1, 1).await.unwrap();
+        set_seed_status(pool, "foo", "done").await.unwrap();
+
+        // same (or deeper) path, same job: skipped
+        enqueue_seed(pool, "foo", "pop", 2, 1).await.unwrap();
+        assert!(next_seed(pool).await.unwrap().is_none());
+
+        // deeper job
+        enqueue_seed(pool, "foo", "pop", 2, 2).await.unwrap();
+        let item = next_seed(pool).await.unwrap().unwrap();
+        assert_eq!((item.tag.as_str(), item.depth, item.max_depth), ("rock", 1, 2));
+
+        // shallower path
+        enqueue_seed(pool, "foo", "pop", 0, 1).await.unwrap();
+        let item = next_seed(pool).await.unwrap().unwrap();
+        assert_eq!((item.tag.as_str(), item.depth, item.max_depth), ("pop", 0, 2));
+    }
 }
@@ -14,55 +14,89 @@ use graphviz_rust::printer::PrinterContext;
 use petgraph::visit::EdgeRef;
 use petgraph::visit::IntoNodeReferences;
 
+use crate::tag_graph::TagGraph;
 use crate::ArtistTree;
 
 fn quote(s: &str) -> String { format!("{:?}", s) }
 
-impl ArtistTree {
-    // https://github.com/egraphs-good/egraph-serialize/blob/5838c036623e91540831745b1574539e01c8cb23/src/graphviz.rs#L36
-    pub fn as_dot(&self) -> graphviz_rust::dot_structures::Graph {
-        let mut stmts = vec![
-            stmt!(GraphAttributes::bgcolor(color_name::transparent)),
-            // confusingly, there is a separate GraphAttributes enum in dot_structures
-            stmt!(GA::Node(vec![
-                NodeAttributes::colorscheme("set36".to_owned()),
-                NodeAttributes::style("filled".to_owned()),
-            ])),
-            stmt!(GA::Edge(vec![
-                EdgeAttributes::color(color_name::grey75),
-                EdgeAttributes::fontcolor(color_name::grey75),
-                // EdgeAttributes::style("bold".to_owned()),
-            ])),
-        ];
-
-        for n in self.graph.node_references() {
-            let url = format!("/artists/{}", n.1); // no need to encode
-            let node = node!(
-                n.0.index();
-                NodeAttributes::label(quote(n.1)),
-                NodeAttributes::URL(quote(&url))
-            );
-            stmts.push(stmt!(node));
-        }
-
-        for e in self.graph.edge_references() {
-            let src = e.source().index();
-            let trg = e.target().index();
-            let edge = edge!(node_id!(src) => node_id!(trg);
-                EdgeAttributes::label(quote(&e.weight().to_string()))
-            );
-            stmts.push(stmt!(edge));
-        }
-
-        graph!(di id!(), stmts)
+/// Shared styling for all graphs rendered by the app. Each node links to
+/// `{url_prefix}{label}`; `style` returns any extra attributes of a node, given
+/// its label.
+// https://github.com/egraphs-good/egraph-serialize/blob/5838c036623e91540831745b1574539e01c8cb23/src/graphviz.rs#L36
+pub fn graph_to_dot(
+    graph: &petgraph::Graph<String, i64>,
+    url_prefix: &str,
+    style: impl Fn(&str) -> Vec<Attribute>,
+) -> Graph {
+    let mut stmts = vec![
+        stmt!(GraphAttributes::bgcolor(color_name::transparent)),
+        // confusingly, there is a separate GraphAttributes enum in dot_structures
+        stmt!(GA::Node(vec![
+            NodeAttributes::colorscheme("set36".to_owned()),
+            NodeAttributes::style("filled".to_owned()),
+        ])),
+        stmt!(GA::Edge(vec![
+            EdgeAttributes::color(color_name::grey75),
+            EdgeAttributes::fontcolor(color_name::grey75),
+            // EdgeAttributes::style("bold".to_owned()),
+        ])),
+    ];
+
+    for n in graph.node_references() {
+        let url = format!("{url_prefix}{}", n.1); // no need to encode
+        let mut node = node!(
+            n.0.index();
+            NodeAttributes::label(quote(n.1)),
+            NodeAttributes::URL(quote(&url))
+        );
+        node.attributes.extend(style(n.1));
+        stmts.push(stmt!(node));
+    }
+
+    for e in graph.edge_references() {
+        let src = e.source().index();
+        let trg = e.target().index();
+        let edge = edge!(node_id!(src) => node_id!(trg);
+            EdgeAttributes::label(quote(&e.weight().to_string()))
+        );
+        stmts.push(stmt!(edge));
     }
 
-    pub fn as_svg(&self) -> String {
-        let dot_str = self.as_dot().print(&mut PrinterContext::default());
-        let args = vec![graphviz_rust::cmd::Format::Svg.into()];
-        let byt = exec_dot(dot_str, args).unwrap();
-        String::from_utf8(byt).unwrap()
+    graph!(di id!(), stmts)
+}
+
+pub fn dot_to_svg(dot: Graph) -> String {
+    let dot_str = dot.print(&mut PrinterContext::default());
+    let args = vec![graphviz_rust::cmd::Format::Svg.into()];
+    let byt = exec_dot(dot_str, args).unwrap();
+    String::from_utf8(byt).unwrap()
+}
+
+impl ArtistTree {
+    /// Nodes left unexpanded in offline mode are dashed; artists already in
+    /// the user's library are coloured, with their playcount as tooltip
+    pub fn as_dot(&self) -> Graph {
+        graph_to_dot(&self.graph, "/artists/", |n| {
+            let mut attrs = vec![];
+            if self.is_uncached(n) {
+                attrs.push(NodeAttributes::style(quote("filled,dashed")));
+            }
+            if let Some(plays) = self.known_plays(n) {
+                // first colour of `set36`
+                attrs.push(Attribute(Id::Plain("fillcolor".to_owned()), Id::Plain("1".to_owned())));
+                attrs.push(NodeAttributes::tooltip(quote(&format!("{plays} plays"))));
+            }
+            attrs
+        })
     }
+
+    pub fn as_svg(&self) -> String { dot_to_svg(self.as_dot()) }
+}
+
+impl TagGraph {
+    pub fn as_dot(&self) -> Graph { graph_to_dot(&self.graph, "/genres/", |_| vec![]) }
+
+    pub fn as_svg(&self) -> String { dot_to_svg(self.as_dot()) }
 }
 
 #[cfg(test)]
@@ -109,4 +143,24 @@ digraph  {
 }"
         );
     }
+
+    #[test]
+    fn known_styling() {
+        let graph = json!({
+            "edge_property":"directed",
+            "edges":[[0,1,100]],
+            "nodes":["Loona","LOOΠΔ 1/3"]
+        });
+
+        let mut tree = ArtistTree::new("foo").with_known([("looπδ 1/3".to_string(), 42)].into());
+        tree.graph = serde_json::from_value(graph).unwrap();
+        assert_eq!(tree.known_plays("LOOΠΔ 1/3"), Some(42));
+        assert_eq!(tree.known_plays("Loona"), None);
+
+        let dot = tree.as_dot().print(&mut PrinterContext::default());
+        assert!(dot.contains(
+            r#"1[label="LOOΠΔ 1/3" URL="/artists/LOOΠΔ 1/3" fillcolor=1 tooltip="42 plays"]"#
+        ));
+        assert!(dot.contains(r#"0[label="Loona" URL="/artists/Loona"]"#));
+    }
 }
@@ -1,12 +1,26 @@
-// will eventually be deprecated
+//! Module for tags (referred to as genres in the UI, since that is what most
+//! tags are). Note that aside from the global chart, last.fm has no concept of
+//! "trending" tags, so these pages are mostly useful as entry points into
+//! artist trees.
 
 use std::fmt::Display;
 
+use maud::html;
+use maud::Markup;
 use serde::Deserialize;
 use serde_json::Value;
+use urlencoding::encode;
 
+use crate::artists::Artist;
+use crate::artists::LastfmError;
+use crate::html;
 use crate::utils::build_lastfm_url;
-use crate::LASTFM_KEY;
+use crate::utils::human_number;
+use crate::utils::throttle;
+use crate::SqPool;
+
+/// Number of tags/artists per page
+const PAGE_SIZE: u32 = 50;
 
 /// Wrapper for `Vec<Genre>`, solely for better error-handling
 #[derive(Deserialize)]
@@ -34,38 +48,205 @@ pub struct Genre {
     // streamable: String,
 }
 
+/// Only the name is used; everything else is fetched via `Artist`
+#[derive(Debug, Deserialize)]
+pub struct GenreArtist {
+    pub name: String,
+}
+
+/// The top artists of a genre, and similar genres
+pub struct GenrePage {
+    pub name: String,
+    pub page: u32,
+    pub total_pages: u32,
+    pub artists: Vec<GenreArtist>,
+    /// Names only
+    pub similar: Vec<String>,
+}
+
 pub async fn get_json(url: &str) -> anyhow::Result<Value> {
+    throttle().await;
     let resp = reqwest::get(url).await?.text().await?;
     Ok(serde_json::from_str::<Value>(&resp)?)
 }
 
+/// `page` starts from 1.
+///
 /// https://www.last.fm/api/show/chart.getTopTags
-// nearly identical to tag.getTopTags
-pub async fn get_top_genres() -> anyhow::Result<Genres> {
-    let url = build_lastfm_url("chart.gettoptags", &LASTFM_KEY, &[])?;
-    let json = get_json(url.as_ref()).await.unwrap();
+// nearly identical to tag.getTopTags, which is not paginated
+pub async fn get_top_genres(
+    page: u32,
+    key: &str,
+) -> anyhow::Result<Genres> {
+    let url = build_lastfm_url(
+        "chart.gettoptags",
+        key,
+        &[
+            ("limit", &PAGE_SIZE.to_string()),
+            ("page", &page.to_string()),
+        ],
+    )?;
+    let json = get_json(url.as_ref()).await?;
 
     let genres = serde_json::from_value(json["tags"]["tag"].clone())?;
 
     Ok(genres)
 }
 
-pub async fn get_genre() {
-    let url = build_lastfm_url("tag.gettopartists", &LASTFM_KEY, &[]).unwrap();
-    let json = get_json(url.as_ref()).await.unwrap();
-    println!("{:?}", json);
+/// `page` starts from 1.
+///
+/// https://www.last.fm/api/show/tag.getTopArtists
+/// https://www.last.fm/api/show/tag.getSimilar
+pub async fn get_genre(
+    tag: &str,
+    page: u32,
+    key: &str,
+) -> anyhow::Result<GenrePage> {
+    let url = build_lastfm_url(
+        "tag.gettopartists",
+        key,
+        &[
+            ("tag", tag),
+            ("limit", &PAGE_SIZE.to_string()),
+            ("page", &page.to_string()),
+        ],
+    )?;
+    let json = get_json(url.as_ref()).await?;
+    let json = &json["topartists"];
+
+    // Last.fm errors have no `topartists`; reported as not found (404)
+    let artists = serde_json::from_value(json["artist"].clone()).map_err(LastfmError::from)?;
+    let total_pages = json["@attr"]["totalPages"]
+        .as_str()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(1);
 
-    // let genres = serde_json::from_value(json["tags"]["tag"].clone())?;
-    // Ok(genres)
+    Ok(GenrePage {
+        name: tag.to_string(),
+        page,
+        total_pages,
+        artists,
+        similar: get_similar_genres(tag, key).await?,
+    })
+}
+
+/// Names of the top `limit` artists of a genre
+///
+/// https://www.last.fm/api/show/tag.getTopArtists
+pub async fn get_genre_artists(
+    tag: &str,
+    limit: u32,
+    key: &str,
+) -> anyhow::Result<Vec<String>> {
+    let url = build_lastfm_url(
+        "tag.gettopartists",
+        key,
+        &[("tag", tag), ("limit", &limit.to_string())],
+    )?;
+    let json = get_json(url.as_ref()).await?;
+
+    let artists: Vec<GenreArtist> = serde_json::from_value(json["topartists"]["artist"].clone())?;
+
+    Ok(artists.into_iter().map(|a| a.name).collect())
+}
+
+/// Note: last.fm often returns an empty list
+///
+/// https://www.last.fm/api/show/tag.getSimilar
+pub async fn get_similar_genres(
+    tag: &str,
+    key: &str,
+) -> anyhow::Result<Vec<String>> {
+    let url = build_lastfm_url("tag.getsimilar", key, &[("tag", tag)])?;
+    let json = get_json(url.as_ref()).await?;
+
+    // unlike `Genre`, no taggings are returned
+    #[derive(Deserialize)]
+    struct SimilarGenre {
+        name: String,
+    }
+
+    let similar: Vec<SimilarGenre> =
+        serde_json::from_value(json["similartags"]["tag"].clone()).unwrap_or_default();
+
+    Ok(similar.into_iter().map(|g| g.name).collect())
+}
+
+pub fn genre_link(tag: &str) -> Markup { html::link(&format!("/genres/{}", encode(tag)), tag) }
+
+impl GenrePage {
+    /// Listeners are only shown if cached; fetching them for every artist
+    /// would take too long.
+    pub async fn as_html(
+        &self,
+        pool: &SqPool,
+    ) -> Markup {
+        let name = &self.name;
+
+        html! {
+            style {
+                "table, th, td { border: 1px solid grey; }"
+            }
+            (html::header(&format!("Genre: {name}")))
+            p {
+                (html::link("/genres", "All genres"))
+                " | "
+                (html::link(&format!("/genres/{}/graph", encode(name)), "Tag graph"))
+            }
+
+            @if !self.similar.is_empty() {
+                p {
+                    "Similar: "
+                    @for (i, tag) in self.similar.iter().enumerate() {
+                        @if i > 0 { ", " }
+                        (genre_link(tag))
+                    }
+                }
+            }
+
+            table {
+                th { "#" }
+                th { "Artist" }
+                th { "Listeners" }
+                @for (i, artist) in self.artists.iter().enumerate() {
+                    @let listeners = Artist::new(&artist.name)
+                        .get_listeners_db(pool)
+                        .await
+                        .ok()
+                        .flatten();
+                    @let cols = vec![
+                        ((self.page - 1) * PAGE_SIZE + i as u32 + 1).to_string(),
+                        html::link(&format!("/artists/{}", encode(&artist.name)), &artist.name).into_string(),
+                        listeners.map(|l| human_number(l as u32)).unwrap_or_default(),
+                    ];
+                    (html::table_row(cols))
+                }
+            }
+
+            (html::pagination(&format!("/genres/{}", encode(name)), self.page, Some(self.total_pages)))
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::get_genre;
     use crate::get_top_genres;
+    use crate::LASTFM_KEY;
 
     #[tokio::test]
     async fn test_get_top_genres() {
-        let g = get_top_genres().await.unwrap();
+        let g = get_top_genres(1, &LASTFM_KEY).await.unwrap();
         assert_eq!(g.0.len(), 50);
+
+        let g2 = get_top_genres(2, &LASTFM_KEY).await.unwrap();
+        assert_ne!(g.0[0].name, g2.0[0].name);
+    }
+
+    #[tokio::test]
+    async fn test_get_genre() {
+        let g = get_genre("death metal", 1, &LASTFM_KEY).await.unwrap();
+        assert_eq!(g.artists.len(), 50);
+        assert!(g.total_pages > 1);
     }
 }
@@ -36,6 +36,65 @@ pub fn api_key_form(redirect_to: &str) -> Markup {
     }
 } //}}}
 
+/// Connected Last.fm users (see `auth`), with buttons to disconnect them
+pub fn lastfm_sessions(names: &[String]) -> Markup {
+    html! {
+        h2 { "Last.fm account" }
+        @for name in names {
+            p {
+                "Connected as " (link(&format!("/charts/{}", encode(name)), name)) " "
+                form style="display: inline" method="POST" action="/lastfm/disconnect" {
+                    input type="hidden" name="name" value=(name) {}
+                    button type="submit" { "Disconnect" }
+                }
+            }
+        }
+        p { (link("/lastfm/connect", "Connect a Last.fm account")) }
+    }
+}
+
+/// Page for managing the shared API key. `key` is already masked. `sessions` is
+/// `None` if Last.fm authentication is not configured.
+pub fn settings_page(
+    key: Option<&str>,
+    date_added: Option<&str>,
+    encrypted: bool,
+    sessions: Option<&[String]>,
+    message: Option<&str>,
+) -> Markup {
+    html! {
+        (header("Settings"))
+        @if let Some(message) = message {
+            p { b { (message) } }
+        }
+        h2 { "Last.fm API key" }
+        @match key {
+            Some(key) => {
+                p {
+                    code { (key) }
+                    @if let Some(date) = date_added { " (added " (date) ")" }
+                }
+                form style="display: inline" method="POST" action="/settings/validate" {
+                    button type="submit" { "Re-validate" }
+                }
+                " "
+                form style="display: inline" method="POST" action="/settings/delete" {
+                    button type="submit" { "Delete" }
+                }
+                h3 { "Replace" }
+                (api_key_form("/settings"))
+            },
+            None => (api_key_form("/settings")),
+        }
+        @if !encrypted {
+            p { "Note: server.secret is not set, so the key is stored in plaintext." }
+        }
+        @if let Some(sessions) = sessions {
+            (lastfm_sessions(sessions))
+        }
+    }
+}
+
 // this could be an ArtistTree method, but only if this gets used a lot
 pub fn get_lastfm_url(name: &str) -> Markup {
     html! {
@@ -79,6 +138,26 @@ pub fn list_item(s: &str) -> Markup {
     }
 }
 
+/// "Previous" and "Next" links. If `total_pages` is unknown, "Next" is always
+/// shown.
+pub fn pagination(
+    path: &str,
+    page: u32,
+    total_pages: Option<u32>,
+) -> Markup {
+    html! {
+        p {
+            @if page > 1 {
+                (link(&format!("{path}?page={}", page - 1), "Previous"))
+                " "
+            }
+            @if !matches!(total_pages, Some(t) if page >= t) {
+                (link(&format!("{path}?page={}", page + 1), "Next"))
+            }
+        }
+    }
+}
+
 pub fn spinner() -> Markup {
     use std::fs;
 
@@ -97,8 +176,9 @@ pub fn spinner() -> Markup {
 impl ArtistTree {
     pub async fn as_html(&self) -> anyhow::Result<Markup> {
         // row order must be independent of graph node order
-        let mut artists: Vec<&String> = self.nodes().filter(|n| **n != self.root).collect();
-        artists.sort_by_key(|a| -self.get_child_similarity(a));
+        let mut artists: Vec<&String> = self.nodes().filter(|n| !self.is_seed(n)).collect();
+        artists.sort_by_key(|a| -self.get_seed_similarity(a));
+        let known = artists.iter().filter(|a| self.known_plays(a).is_some()).count();
 
         // if true {
         //     artists.sort_by_key(|a| -self.get_child_similarity(a));
@@ -108,7 +188,6 @@ impl ArtistTree {
 
         // TODO: /artists/X/json endpoint to re-export youtube results (for piping into
         // mpv)
-        // TODO: new column + button to "ignore" artists (new table)
 
         // Note that the normal pattern of POST/redirect/GET, which is needed to avoid
         // problems with page refresh and form re-submission, is not needed in
@@ -134,6 +213,21 @@ impl ArtistTree {
             }
         }
 
+        /// Add `artist` to one of the logged-in account's lists; the button is
+        /// replaced by the response
+        fn list_button(
+            list: &str,
+            label: &str,
+            artist: &str,
+        ) -> Markup {
+            html! {
+                button
+                    hx-post={"/account/"(list)"/"(encode(artist))}
+                    hx-swap="outerHTML"
+                { (label) }
+            }
+        }
+
         // TODO: right align Similarity values (but not header)
         // https://stackoverflow.com/a/1332648
 
@@ -145,22 +239,38 @@ impl ArtistTree {
         let cols: Vec<ColumnGenerator> = vec![
             (
                 "Similarity",
-                Box::new(|artist| self.get_child_similarity(artist).to_string()),
+                Box::new(|artist| self.get_seed_similarity(artist).to_string()),
             ),
             (
                 "Artist",
                 Box::new(|artist| {
-                    link(&format!("/artists/{}", encode(artist)), artist).into_string()
+                    let link = link(&format!("/artists/{}", encode(artist)), artist);
+                    html! {
+                        (link)
+                        @if self.is_uncached(artist) { " " i { "(not cached)" } }
+                        @if let Some(plays) = self.known_plays(artist) {
+                            " " mark { "known · "(plays)" plays" }
+                        }
+                    }
+                    .into_string()
                 }),
             ),
             (
                 "Links",
                 Box::new(|artist| {
-                    format!(
+                    let links = format!(
                         "{} {}",
                         link(&format!("https://last.fm/music/{artist}"), "Last.fm").into_string(),
                         yt_button(artist).into_string(),
-                    )
+                    );
+                    match self.has_account_actions() {
+                        true => format!(
+                            "{links} {} {}",
+                            list_button("favourite", "Favourite", artist).into_string(),
+                            list_button("ignored", "Ignore", artist).into_string(),
+                        ),
+                        false => links,
+                    }
                 }),
             ),
             // // TODO: this requires a new db table, so think twice
@@ -196,7 +306,14 @@ impl ArtistTree {
                 @for artist in artists {
                     // @let cols: Vec<Markup> = cols.iter().map(|x| &x.1).map(|f| f(artist)).collect();
                     // @let cols = cols.iter().map(|x| (x.1)(artist));
-                    (table_row(cols.iter().map(|x| (x.1)(artist)).collect()))
+                    @let row = cols.iter().map(|x| (x.1)(artist)).collect();
+                    @if self.known_plays(artist).is_some() {
+                        tr class="known" {
+                            @for s in row { td { (PreEscaped(s)) } }
+                        }
+                    } @else {
+                        (table_row(row))
+                    }
                 }
             }
         };
@@ -206,8 +323,14 @@ impl ArtistTree {
                 script src="https://unpkg.com/htmx.org@1.9.12" {}
                 style {
                     "table, th, td { border: 1px solid grey; }"
+                    // pure css toggle; the checkbox must precede the table
+                    "#hide-known:checked ~ table tr.known { display: none; }"
+                }
+                @if self.seeds().len() > 1 {
+                    (header(&format!("Artists: {}", self.seeds().join(", "))))
+                } @else {
+                    (header(&format!("Artist: {}", self.root)))
                 }
-                (header(&format!("Artist: {}", self.root)))
                 // TODO: show artist's tags (with links?)
                 // h1 { (get_lastfm_url(&self.root)) }
                 body {
@@ -218,12 +341,26 @@ impl ArtistTree {
                         summary { "Tree" }
                         (PreEscaped(&self.as_svg()))
                     }
+                    @if self.is_filtered() {
+                        p { (self.pruned()) " candidates pruned by filters" }
+                    }
+                    @if self.is_offline() {
+                        p {
+                            "Offline mode: only cached data is shown. "
+                            (self.uncached()) " artists (dashed) have no cached similar artists."
+                        }
+                    }
                     span class="htmx-indicator" {
                         img width="20" src=(spinner()) {}
                         // TODO: inject value from yt_button into this string?
                         "Searching..."
                     }
                     span id="player" { }
+                    @if self.has_known() {
+                        p { (known) " artists are already in your library." }
+                        input type="checkbox" id="hide-known" checked[self.hides_known()];
+                        label for="hide-known" { " Hide known artists" }
+                    }
                     (table)
                 }
             }
@@ -12,17 +12,32 @@
 //
 // https://users.rust-lang.org/t/principles-for-using-mod-vs-pub-mod/27814/2
 
+pub mod accounts;
+pub mod api;
 mod artists;
+pub mod auth;
 pub mod charts;
+pub mod compare;
+pub mod config;
 mod db;
 pub mod dot;
 mod genres;
 pub mod html;
 mod player;
+pub mod profile;
+pub mod recommend;
 pub mod routes;
+pub mod scrobbles;
+pub mod seed;
+mod secret;
+pub mod stats;
+pub mod tag_graph;
 pub mod tests;
 mod tree;
+pub mod trends;
 pub mod utils;
+use config::config;
+use config::Config;
 pub use db::*;
 pub use genres::*;
 pub use tree::*;
@@ -30,15 +45,23 @@ pub use tree::*;
 lazy_static::lazy_static! {
     static ref WEEK: u32 = 60 * 60 * 24 * 7;
 
+    // shorthands for frequently used `Config` values. `Config::validate` ensures
+    // that the `expect`s never fail at runtime (except in tests)
+
     /// Used only for testing
-    static ref LASTFM_USER: String =
-        std::env::var("LASTFM_USER").expect("Environment variable $LASTFM_USER must be set");
-    static ref LASTFM_KEY: String =
-        std::env::var("LASTFM_KEY").expect("Environment variable $LASTFM_KEY must be set");
+    static ref LASTFM_USER: String = config().lastfm.default_user.clone()
+        .expect("lastfm.default_user (or $LASTFM_USER) must be set");
+    /// Used only for testing; elsewhere, see `lastfm_key`
+    static ref LASTFM_KEY: String = config().lastfm.key.clone()
+        .expect("lastfm.key (or $LASTFM_KEY) must be set");
+
+    static ref LASTFM_URL: String = config().lastfm.base_url.clone();
 
-    static ref LASTFM_URL: String = "http://ws.audioscrobbler.com/2.0/?format=json".to_string();
+    static ref APP_NAME: String = config().server.app_name.clone();
 
-    static ref APP_NAME: String = "Last".to_string();
+    /// If set, no requests are made to Last.fm for artist data; only cached
+    /// data is used
+    static ref OFFLINE: bool = config().lastfm.offline;
 
     /// A base64 engine used to crudely pass data from one endpoint to another
     static ref BASE64: base64::engine::GeneralPurpose = base64::engine::GeneralPurpose::new(
@@ -47,25 +70,28 @@ lazy_static::lazy_static! {
     );
 }
 
+/// Pending migrations are applied before the server is started (see
+/// `run_migrations`). `config` is validated and made available globally (see
+/// `Config::init`); it is also available to routes as `web::Data<Config>`.
+///
 /// To start the server:
 /// ```no_run
+/// use lasttree::config::Config;
 /// use lasttree::init_server;
 ///
 /// # tokio_test::block_on(async {
-/// let server = init_server("db_url", 7777).unwrap();
+/// let config = Config::load(None).unwrap();
+///
+/// let server = init_server(config.clone()).await.unwrap();
 /// server.await.unwrap(); // in production
 ///
-/// let server = init_server("db_url", 7777).unwrap();
+/// let server = init_server(config).await.unwrap();
 /// tokio::spawn(server); // in test
 /// # })
 /// ```
-pub fn init_server(
-    db_url: &str,
-    port: u16,
-) -> anyhow::Result<actix_web::dev::Server> {
+pub async fn init_server(config: Config) -> anyhow::Result<actix_web::dev::Server> {
     // use actix_web::dev::Server;
     use actix_web::web;
-    use actix_web::App;
     use actix_web::HttpServer;
 
     // let addr = format!("{}:{}", "127.0.0.1", 3838);
@@ -73,37 +99,112 @@ pub fn init_server(
 
     // https://github.com/actix/examples/blob/6334049545e0a03888b4dc57a9d447e0292164ee/databases/sqlite/src/main.rs#L51
 
-    let pool = web::Data::new(init_db(db_url)?);
-
-    let server = HttpServer::new(move || {
-        App::new()
-            // i prefer
-            //      .service(foo) + #[get("/foo")] fn foo
-            // over
-            //      .route("/foo", web::get().to(foo))
-            // because it keeps `App` clean, and the route is more closely coupled to the function
-            // https://actix.rs/docs/url-dispatch/#scoping-routes
-            //
-            // .route("/", web::get().to(home))
-            .service(routes::home)
-            .service(routes::search_artists)
-            .service(routes::post_artists)
-            .service(routes::show_artist)
-            // .service(routes::genres)
-            .service(routes::get_charts)
-            .service(routes::get_charts_user)
-            .service(routes::get_charts_null)
-            // TODO: how to get compiler to remind me to use POST routes?
-            .service(routes::post_charts)
-            // .service(web::resource(["/charts/", "/charts/{user}"]).to(routes::get_charts))
-            // .service(web::resource("/charts/").to(routes::get_charts))
-            // auxiliary
-            .service(routes::login)
-            .service(routes::search_youtube)
-            .default_service(web::route().to(routes::not_found))
-            .app_data(pool.clone())
-    })
-    .bind(("127.0.0.1", port))?
-    .run();
+    // helpers that are not given the config (e.g. `lastfm_key`, `secret`) read
+    // the global one, so it must be the same as the one served
+    let config = config.init()?.clone();
+
+    let db_url = &config.database.url;
+    let pool = init_db(db_url)?;
+    run_migrations(&pool, db_url, config.database.backup).await?;
+    match config.server.secret {
+        Some(_) => {
+            let n = encrypt_stored_keys(&pool).await?;
+            if n > 0 {
+                println!("Encrypted {n} stored API keys");
+            }
+        }
+        None => eprintln!("server.secret is not set; API keys will be stored in plaintext"),
+    }
+    let pool = web::Data::new(pool);
+
+    let addr = (config.server.bind.clone(), config.server.port);
+    let config = web::Data::new(config);
+
+    let server = HttpServer::new(move || init_app(pool.clone(), config.clone()))
+        .bind(addr)?
+        .run();
     Ok(server)
 }
+
+/// The app served by `init_server`. Tests should use it directly (with
+/// `actix_web::test`), since only one config can be installed globally per
+/// process.
+pub fn init_app(
+    pool: actix_web::web::Data<SqPool>,
+    config: actix_web::web::Data<Config>,
+) -> actix_web::App<
+    impl actix_web::dev::ServiceFactory<
+        actix_web::dev::ServiceRequest,
+        Config = (),
+        Response = actix_web::dev::ServiceResponse,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+> {
+    use actix_web::web;
+    use actix_web::App;
+
+    let accounts = config.server.accounts;
+
+    App::new()
+        // i prefer
+        //      .service(foo) + #[get("/foo")] fn foo
+        // over
+        //      .route("/foo", web::get().to(foo))
+        // because it keeps `App` clean, and the route is more closely coupled to the function
+        // https://actix.rs/docs/url-dispatch/#scoping-routes
+        //
+        // .route("/", web::get().to(home))
+        .service(routes::home)
+        .service(routes::search_artists)
+        .service(routes::post_artists)
+        .service(routes::show_artist)
+        .service(routes::genres)
+        .service(routes::show_genre)
+        .service(routes::show_tag_graph)
+        .service(routes::get_charts_window)
+        .service(routes::get_charts_local)
+        .service(routes::get_stats)
+        .service(routes::get_trends)
+        .service(routes::get_charts)
+        .service(routes::get_charts_user)
+        .service(routes::get_charts_null)
+        // TODO: how to get compiler to remind me to use POST routes?
+        .service(routes::post_charts)
+        .service(routes::get_chart_tags)
+        .service(routes::get_recommendations)
+        .service(routes::get_comparison)
+        // .service(web::resource(["/charts/", "/charts/{user}"]).to(routes::get_charts))
+        // .service(web::resource("/charts/").to(routes::get_charts))
+        // auxiliary
+        .configure(|cfg| {
+            if accounts {
+                cfg.service(routes::register_form)
+                    .service(routes::register)
+                    .service(routes::login_form)
+                    .service(routes::account_login)
+                    .service(routes::logout)
+                    .service(routes::show_account)
+                    .service(routes::post_account)
+                    .service(routes::remove_account_artist)
+                    .service(routes::add_account_artist);
+            } else {
+                // the shared key; with accounts, each account manages its
+                // own key via `/account`
+                cfg.service(routes::login)
+                    .service(routes::settings)
+                    .service(routes::validate_settings)
+                    .service(routes::delete_settings_key);
+            }
+        })
+        .service(routes::admin_seed)
+        .service(routes::post_admin_seed)
+        .service(routes::lastfm_connect)
+        .service(routes::lastfm_callback)
+        .service(routes::lastfm_disconnect)
+        .service(routes::search_youtube)
+        .service(web::scope("/api/v1").configure(api::config))
+        .default_service(web::route().to(routes::not_found))
+        .app_data(pool)
+        .app_data(config)
+}
@@ -1,12 +1,308 @@
-use std::env;
+use std::path::PathBuf;
 
+use chrono::NaiveDate;
+use chrono::NaiveTime;
+use clap::Parser;
+use clap::Subcommand;
+use clap::ValueEnum;
+use graphviz_rust::printer::DotPrinter;
+use graphviz_rust::printer::PrinterContext;
+use lasttree::auth::LastfmAuth;
+use lasttree::auth::LastfmSession;
+use lasttree::charts::Period;
+use lasttree::charts::User;
+use lasttree::config::Config;
+use lasttree::encrypt_stored_keys;
+use lasttree::get_lastfm_session;
+use lasttree::init_db;
 use lasttree::init_server;
+use lasttree::run_migrations;
+use lasttree::scrobbles::parse_export;
+use lasttree::seed::seed_genres;
+use lasttree::seed::warm_artist;
+use lasttree::seed::SeedOptions;
+use lasttree::store_api_key;
+use lasttree::store_lastfm_session;
+use lasttree::store_scrobbles;
+use lasttree::ArtistTree;
+
+/// Without a subcommand, the server is started with default options. All
+/// subcommands other than `serve` print to stdout, so they can be piped into
+/// other tools.
+///
+/// Settings are read from `lasttree.toml` (if present) and the environment;
+/// see `lasttree.example.toml`. Arguments take precedence over both.
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Config file (default: ./lasttree.toml, if it exists)
+    #[arg(long, env = "LASTTREE_CONFIG", global = true)]
+    config: Option<PathBuf>,
+
+    /// Overrides `database.url`
+    #[arg(long, global = true)]
+    db: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Start the web server
+    Serve {
+        /// Overrides `server.bind`
+        #[arg(long)]
+        bind: Option<String>,
+
+        /// Overrides `server.port`
+        #[arg(long)]
+        port: Option<u16>,
+
+        /// Back up the db file before applying pending migrations
+        #[arg(long)]
+        backup: bool,
+    },
+
+    /// Print the tree of an artist
+    Tree {
+        artist: String,
+
+        #[arg(long, value_enum, default_value_t = TreeFormat::Outline)]
+        format: TreeFormat,
+
+        /// Only use cached data
+        #[arg(long)]
+        offline: bool,
+    },
+
+    /// Print a user's top artists as tab-separated rank, artist, playcount
+    Chart {
+        user: String,
+
+        /// One of: 7day, 1month, 3month, 6month, 12month, overall
+        #[arg(default_value = "overall")]
+        period: String,
+
+        #[arg(long, default_value_t = 50)]
+        limit: u32,
+
+        /// Start of a window (YYYY-MM-DD), computed from imported scrobbles;
+        /// `period` is ignored
+        #[arg(long)]
+        from: Option<NaiveDate>,
+
+        /// End of a window (YYYY-MM-DD, exclusive); see `--from`
+        #[arg(long)]
+        to: Option<NaiveDate>,
+    },
+
+    /// Import a user's scrobbles from a CSV or JSON export, or (without a
+    /// file) fetch any new scrobbles from Last.fm
+    Import {
+        user: String,
+
+        file: Option<PathBuf>,
+    },
+
+    /// Prefill the cache with an artist's tree, or (with `--tag`) the top
+    /// artists of a genre. Genre jobs are resumable.
+    Warm {
+        name: String,
+
+        /// Treat `name` as a tag
+        #[arg(long)]
+        tag: bool,
+
+        /// Number of top artists (`--tag` only)
+        #[arg(long, default_value_t = 25)]
+        limit: u32,
+
+        /// Depth of similar artists to seed (`--tag` only)
+        #[arg(long, default_value_t = 1)]
+        depth: u8,
+    },
+
+    /// Apply pending database migrations (this is also done by `serve`)
+    Migrate {
+        /// Back up the db file first, if there is anything to apply
+        #[arg(long)]
+        backup: bool,
+    },
+
+    /// Validate a Last.fm API key, then store it, replacing any existing one
+    SetKey { key: String },
+
+    /// Connect a Last.fm account (requires `lastfm.shared_secret`), so that
+    /// requests for its charts are authenticated
+    Auth,
+
+    /// Love a track, as a connected Last.fm user
+    Love {
+        artist: String,
+        track: String,
+
+        /// Default: `lastfm.default_user`
+        #[arg(long)]
+        user: Option<String>,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum TreeFormat {
+    Outline,
+    Json,
+    Dot,
+}
 
 #[tokio::main] // requires tokio features: macros, rt-multi-thread
 async fn main() -> anyhow::Result<()> {
-    let db_url = env::var("DATABASE_URL").unwrap_or("sqlite://lasttree.db".to_owned());
-    let port = 3838;
-    init_server(&db_url, port)?.await?;
+    let cli = Cli::parse();
+    let command = cli.command.unwrap_or(Command::Serve {
+        bind: None,
+        port: None,
+        backup: false,
+    });
+
+    let mut config = Config::load(cli.config.as_deref())?;
+    if let Some(db) = cli.db {
+        config.database.url = db;
+    }
+    if let Command::Serve { bind, port, backup } = &command {
+        if let Some(bind) = bind {
+            config.server.bind = bind.clone();
+        }
+        if let Some(port) = port {
+            config.server.port = *port;
+        }
+        config.database.backup |= backup;
+    }
+    let config = config.init()?;
+    let db_url = &config.database.url;
+
+    // connections are only opened when needed
+    let pool = init_db(db_url)?;
+    // `serve` migrates (and optionally backs up) the db itself
+    if !matches!(command, Command::Serve { .. } | Command::Migrate { .. }) {
+        run_migrations(&pool, db_url, false).await?;
+    }
+
+    match command {
+        Command::Serve { .. } => init_server(config.clone()).await?.await?,
+        Command::Tree {
+            artist,
+            format,
+            offline,
+        } => {
+            let tree = ArtistTree::new(&artist)
+                .with_offline(offline)
+                .build_tree(&pool)
+                .await?;
+            let out = match format {
+                TreeFormat::Outline => tree.as_outline(),
+                TreeFormat::Json => tree.as_json()?,
+                TreeFormat::Dot => tree.as_dot().print(&mut PrinterContext::default()),
+            };
+            println!("{out}");
+        }
+        Command::Chart {
+            user,
+            period,
+            limit,
+            from,
+            to,
+        } => {
+            let user = User::for_owner(&user, &pool).await?;
+            let chart = match (from, to) {
+                (None, None) => {
+                    let period = Period::try_from(period.as_str()).map_err(anyhow::Error::msg)?;
+                    user.get_chart_period(period, limit, 1).await?
+                }
+                _ => {
+                    let ts = |d: NaiveDate| d.and_time(NaiveTime::MIN).and_utc().timestamp() as u64;
+                    user.get_local_chart(from.map(ts), to.map(ts), limit, 1, &pool)
+                        .await?
+                }
+            };
+            println!("{}", chart.as_tsv());
+        }
+        Command::Import { user, file } => {
+            let n = match file {
+                Some(file) => {
+                    let text = std::fs::read_to_string(&file)?;
+                    let scrobbles = parse_export(&text)?;
+                    eprintln!("Parsed {} scrobbles", scrobbles.len());
+                    store_scrobbles(&pool, &user, &scrobbles).await?
+                }
+                None => {
+                    User::for_owner(&user, &pool)
+                        .await?
+                        .import_recent_tracks(&pool)
+                        .await?
+                }
+            };
+            eprintln!("Imported {n} new scrobbles");
+        }
+        Command::Warm {
+            name,
+            tag,
+            limit,
+            depth,
+        } => {
+            let n = match tag {
+                true => {
+                    let opts = SeedOptions {
+                        tags: vec![name],
+                        limit,
+                        depth,
+                    };
+                    seed_genres(&pool, &opts).await?
+                }
+                false => warm_artist(&pool, &name).await?,
+            };
+            eprintln!("Cached {n} artists");
+        }
+        Command::Migrate { backup } => {
+            run_migrations(&pool, db_url, backup).await?;
+            let n = encrypt_stored_keys(&pool).await?;
+            if n > 0 {
+                eprintln!("Encrypted {n} stored API keys");
+            }
+        }
+        Command::SetKey { key } => store_api_key(&pool, &key).await?,
+        Command::Auth => {
+            let auth = LastfmAuth::from_config().ok_or(anyhow::anyhow!(
+                "lastfm.shared_secret (or $LASTFM_SECRET) must be set"
+            ))?;
+            let token = auth.get_token().await?;
+            eprintln!(
+                "Grant access at the following URL, then press Enter:\n{}",
+                auth.authorize_url(Some(&token), None)?
+            );
+            std::io::stdin().read_line(&mut String::new())?;
+
+            let session = auth.get_session(&token).await?;
+            store_lastfm_session(&pool, &session, None).await?;
+            eprintln!("Connected as {}", session.name);
+        }
+        Command::Love {
+            artist,
+            track,
+            user,
+        } => {
+            let auth = LastfmAuth::from_config().ok_or(anyhow::anyhow!(
+                "lastfm.shared_secret (or $LASTFM_SECRET) must be set"
+            ))?;
+            let name = user
+                .or(config.lastfm.default_user.clone())
+                .ok_or(anyhow::anyhow!("No user given"))?;
+            let key = get_lastfm_session(&pool, &name, None)
+                .await?
+                .ok_or(anyhow::anyhow!("{name} is not connected; run `lasttree auth` first"))?;
+            auth.love_track(&LastfmSession { name, key }, &artist, &track)
+                .await?;
+        }
+    }
 
     Ok(())
 }
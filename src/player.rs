@@ -18,6 +18,8 @@ use youtube_dl::SearchOptions;
 use youtube_dl::SingleVideo;
 use youtube_dl::YoutubeDl;
 
+use crate::config::config;
+
 #[derive(Debug)]
 pub struct YoutubeAudio {
     /// Decrypted googlevideo link
@@ -26,10 +28,16 @@ pub struct YoutubeAudio {
     pub title: String,
 }
 
+/// See the `player` config section
 pub async fn search_youtube(query: &str) -> anyhow::Result<YoutubeAudio> {
-    let n = 5;
+    let player = &config().player;
+    let n = player.search_results;
     let opts = SearchOptions::youtube(query).with_count(n);
-    let results = YoutubeDl::search_for(&opts)
+    let mut search = YoutubeDl::search_for(&opts);
+    if let Some(path) = &player.ytdlp_path {
+        search.youtube_dl_path(path);
+    }
+    let results = search
         .run_async()
         .await?
         .into_playlist()
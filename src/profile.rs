@@ -0,0 +1,190 @@
+//! Module for aggregating the tags of a user's chart into a genre profile.
+
+use std::collections::HashMap;
+
+use maud::html;
+use maud::Markup;
+use serde_json::json;
+use serde_json::Value;
+use strum::IntoEnumIterator;
+use urlencoding::encode;
+
+use crate::accounts::Account;
+use crate::artists::Artist;
+use crate::charts::Period;
+use crate::charts::User;
+use crate::html;
+use crate::SqPool;
+
+/// Number of tags shown in the bar chart (the table is not truncated)
+const BARS: usize = 20;
+
+pub struct TagProfile {
+    user: String,
+    period: Period,
+
+    /// Tags (lowercase) and their weights, in descending weight. Weights sum to
+    /// 1.
+    pub tags: Vec<(String, f64)>,
+}
+
+/// Each tag of an artist is weighted by the artist's playcount. Tags are
+/// lowercased, since last.fm tags are case-insensitive.
+fn aggregate(artists: &[(u64, Vec<String>)]) -> Vec<(String, f64)> {
+    let mut weights: HashMap<String, f64> = HashMap::new();
+    for (playcount, tags) in artists {
+        for tag in tags {
+            *weights.entry(tag.to_lowercase()).or_default() += *playcount as f64;
+        }
+    }
+
+    let total: f64 = weights.values().sum();
+    let mut tags: Vec<(String, f64)> = weights
+        .into_iter()
+        .map(|(tag, w)| (tag, w / total))
+        .collect();
+    tags.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    tags
+}
+
+impl TagProfile {
+    /// Based on the user's top `limit` artists
+    pub async fn new(
+        username: &str,
+        period: Period,
+        limit: u32,
+        account: Option<&Account>,
+        pool: &SqPool,
+    ) -> anyhow::Result<Self> {
+        let user = User::new(username, account, pool).await?;
+        let chart = user.get_chart_period(period, limit, 1).await?;
+
+        let mut artists = vec![];
+        for artist in chart.artists {
+            let tags = Artist::new(&artist.name)
+                .with_key(account.and_then(|a| a.api_key.clone()))
+                .get_tags(pool)
+                .await
+                .unwrap_or_default();
+            artists.push((artist.playcount, tags));
+        }
+
+        Ok(Self {
+            user: user.username().to_string(),
+            period,
+            tags: aggregate(&artists),
+        })
+    }
+
+    /// Used by the JSON API
+    pub fn as_json(&self) -> Value {
+        let tags: Vec<Value> = self
+            .tags
+            .iter()
+            .map(|(tag, weight)| json!({ "name": tag, "weight": weight }))
+            .collect();
+        json!({
+            "user": self.user,
+            "period": self.period.to_string(),
+            "tags": tags,
+        })
+    }
+
+    fn as_svg(&self) -> Markup {
+        let (bar_height, label_width, bar_width) = (20, 200, 400);
+        let tags: Vec<&(String, f64)> = self.tags.iter().take(BARS).collect();
+        let max = tags.first().map(|t| t.1).unwrap_or(1.0);
+
+        html! {
+            svg
+                xmlns="http://www.w3.org/2000/svg"
+                width=(label_width + bar_width + 60)
+                height=(bar_height * tags.len())
+                {
+                    @for (i, (tag, weight)) in tags.iter().enumerate() {
+                        @let y = i * bar_height;
+                        a href=(tag_url(tag)) {
+                            text x=(label_width - 5) y=(y + 15) text-anchor="end" { (tag) }
+                        }
+                        rect
+                            x=(label_width)
+                            y=(y + 3)
+                            width=(format!("{:.1}", weight / max * bar_width as f64))
+                            height=(bar_height - 6)
+                            fill="steelblue"
+                            {}
+                        text
+                            x=(format!("{:.1}", label_width as f64 + weight / max * bar_width as f64 + 5.0))
+                            y=(y + 15)
+                            { (format!("{:.1}%", weight * 100.0)) }
+                    }
+                }
+        }
+    }
+
+    pub fn as_html(&self) -> Markup {
+        let user = &self.user;
+
+        let periods = html! {
+            @for p in Period::iter() {
+                @if p == self.period {
+                    b { (p) }
+                } @else {
+                    (html::link(&format!("/charts/{user}/{p}/tags"), &p.to_string()))
+                }
+                " "
+            }
+        };
+
+        html! {
+            style {
+                "table, th, td { border: 1px solid grey; }"
+            }
+            (html::header(&format!("Tags for {user}")))
+            p { (html::link(&format!("/charts/{user}/{}", self.period), "Chart")) }
+            (periods)
+
+            details open {
+                summary { "Profile" }
+                (self.as_svg())
+            }
+
+            table {
+                th { "#" }
+                th { "Tag" }
+                th { "Weight" }
+                @for (i, (tag, weight)) in self.tags.iter().enumerate() {
+                    @let cols = vec![
+                        (i + 1).to_string(),
+                        html::link(&tag_url(tag), tag).into_string(),
+                        format!("{:.1}%", weight * 100.0),
+                    ];
+                    (html::table_row(cols))
+                }
+            }
+        }
+    }
+}
+
+fn tag_url(tag: &str) -> String { format!("/genres/{}", encode(tag)) }
+
+#[cfg(test)]
+mod tests {
+    use super::aggregate;
+
+    #[test]
+    fn test_aggregate() {
+        let artists = vec![
+            (30, vec!["Rock".to_string(), "metal".to_string()]),
+            (10, vec!["rock".to_string(), "pop".to_string()]),
+        ];
+        assert_eq!(
+            aggregate(&artists),
+            [
+                ("rock".to_string(), 0.5),
+                ("metal".to_string(), 0.375),
+                ("pop".to_string(), 0.125),
+            ]
+        );
+    }
+}
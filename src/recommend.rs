@@ -0,0 +1,248 @@
+//! Module for recommending artists based on a user's chart. The similar
+//! artists of each top artist are pooled together, and artists the user
+//! already listens to are discarded.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use indexmap::IndexMap;
+use maud::html;
+use maud::Markup;
+use maud::PreEscaped;
+use serde_json::json;
+use serde_json::Value;
+use strum::IntoEnumIterator;
+use urlencoding::encode;
+
+use crate::accounts::Account;
+use crate::artists::Artist;
+use crate::charts::Period;
+use crate::charts::User;
+use crate::html;
+use crate::ArtistTree;
+use crate::SqPool;
+
+/// Number of recommendations shown in the graph (the table is not truncated)
+const GRAPH_SIZE: usize = 20;
+
+pub struct Recommendation {
+    pub name: String,
+
+    /// Sum of `playcount * similarity` over all top artists (similarity is
+    /// normalised to 1)
+    pub score: f64,
+
+    /// Top artists that led to this recommendation, with their similarity (0 to
+    /// 100), in descending similarity
+    pub sources: Vec<(String, i64)>,
+}
+
+pub struct Recommendations {
+    user: String,
+    period: Period,
+
+    /// Descending score
+    pub artists: Vec<Recommendation>,
+}
+
+/// Pool the similar artists of each top artist (given as `(name, playcount,
+/// similars)`), weighted by playcount and similarity. Top artists and `known`
+/// artists (compared case-insensitively) are excluded.
+fn rank(
+    top: &[(String, u64, IndexMap<String, i64>)],
+    known: &HashSet<String>,
+) -> Vec<Recommendation> {
+    let known: HashSet<String> = known
+        .iter()
+        .chain(top.iter().map(|t| &t.0))
+        .map(|a| a.to_lowercase())
+        .collect();
+
+    let mut recs: HashMap<&str, Recommendation> = HashMap::new();
+    for (parent, playcount, similars) in top {
+        for (child, sim) in similars {
+            if known.contains(&child.to_lowercase()) {
+                continue;
+            }
+            let rec = recs.entry(child.as_str()).or_insert(Recommendation {
+                name: child.to_string(),
+                score: 0.0,
+                sources: vec![],
+            });
+            rec.score += *playcount as f64 * *sim as f64 / 100.0;
+            rec.sources.push((parent.to_string(), *sim));
+        }
+    }
+
+    let mut recs: Vec<Recommendation> = recs.into_values().collect();
+    for rec in recs.iter_mut() {
+        rec.sources.sort_by_key(|s| -s.1);
+    }
+    recs.sort_by(|a, b| b.score.total_cmp(&a.score));
+    recs
+}
+
+impl Recommendations {
+    /// Recommendations are based on the user's top `limit` artists.
+    ///
+    /// Note: this requires one request per top artist (unless cached), and one
+    /// more for the user's known artists
+    pub async fn new(
+        username: &str,
+        period: Period,
+        limit: u32,
+        account: Option<&Account>,
+        pool: &SqPool,
+    ) -> anyhow::Result<Self> {
+        let user = User::new(username, account, pool).await?;
+        let chart = user.get_chart_period(period, limit, 1).await?;
+        let known = user.get_known_artists().await?;
+
+        let mut top = vec![];
+        for artist in chart.artists {
+            let similars = Artist::new(&artist.name)
+                .with_key(account.and_then(|a| a.api_key.clone()))
+                .get_similar_artists(pool)
+                .await?;
+            top.push((artist.name, artist.playcount, similars));
+        }
+
+        Ok(Self {
+            user: user.username().to_string(),
+            period,
+            artists: rank(&top, &known),
+        })
+    }
+
+    /// Used by the JSON API
+    pub fn as_json(&self) -> Value {
+        let artists: Vec<Value> = self
+            .artists
+            .iter()
+            .map(|rec| {
+                let sources: Vec<Value> = rec
+                    .sources
+                    .iter()
+                    .map(|(name, sim)| json!({ "name": name, "similarity": sim }))
+                    .collect();
+                json!({ "name": rec.name, "score": rec.score, "sources": sources })
+            })
+            .collect();
+        json!({
+            "user": self.user,
+            "period": self.period.to_string(),
+            "artists": artists,
+        })
+    }
+
+    /// Top artists (i.e. sources) as seeds, connected to the top recommendations
+    fn as_tree(&self) -> ArtistTree {
+        let mut seeds: Vec<String> = vec![];
+        for rec in self.artists.iter().take(GRAPH_SIZE) {
+            for (source, _) in rec.sources.iter() {
+                if !seeds.contains(source) {
+                    seeds.push(source.to_string());
+                }
+            }
+        }
+
+        if seeds.is_empty() {
+            return ArtistTree::new(&self.user);
+        }
+
+        let mut tree = ArtistTree::from_seeds(&seeds);
+        for rec in self.artists.iter().take(GRAPH_SIZE) {
+            for (source, sim) in rec.sources.iter() {
+                tree.add_pair(source, &rec.name, *sim);
+            }
+        }
+        tree
+    }
+
+    pub fn as_html(&self) -> Markup {
+        let user = &self.user;
+
+        let periods = html! {
+            @for p in Period::iter() {
+                @if p == self.period {
+                    b { (p) }
+                } @else {
+                    (html::link(&format!("/recommend/{user}/{p}"), &p.to_string()))
+                }
+                " "
+            }
+        };
+
+        html! {
+            style {
+                "table, th, td { border: 1px solid grey; }"
+            }
+            (html::header(&format!("Recommendations for {user}")))
+            p { (html::link(&format!("/charts/{user}/{}", self.period), "Chart")) }
+            (periods)
+
+            details open {
+                summary { "Graph" }
+                (PreEscaped(&self.as_tree().as_svg()))
+            }
+
+            table {
+                th { "#" }
+                th { "Artist" }
+                th { "Score" }
+                th { "Similar to" }
+
+                @for (i, rec) in self.artists.iter().enumerate() {
+                    @let name = &rec.name;
+                    @let cols = vec![
+                        (i + 1).to_string(),
+                        html::link(&format!("/artists/{}", encode(name)), name).into_string(),
+                        format!("{:.0}", rec.score),
+                        rec.sources
+                            .iter()
+                            .map(|(s, sim)| format!("{s} ({sim})"))
+                            .collect::<Vec<String>>()
+                            .join(", "),
+                    ];
+                    (html::table_row(cols))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use indexmap::IndexMap;
+
+    use super::rank;
+
+    #[test]
+    fn test_rank() {
+        let similars = |pairs: &[(&str, i64)]| -> IndexMap<String, i64> {
+            pairs.iter().map(|(a, s)| (a.to_string(), *s)).collect()
+        };
+        let top = vec![
+            (
+                "A".to_string(),
+                100,
+                similars(&[("B", 100), ("X", 90), ("Y", 50)]),
+            ),
+            ("B".to_string(), 10, similars(&[("A", 100), ("Y", 100)])),
+        ];
+        let known = HashSet::from(["x".to_string()]);
+
+        let recs = rank(&top, &known);
+
+        // top artists and known artists are excluded
+        assert_eq!(recs.len(), 1);
+        assert_eq!(recs[0].name, "Y");
+        assert_eq!(recs[0].score, 60.0);
+        assert_eq!(
+            recs[0].sources,
+            [("B".to_string(), 100), ("A".to_string(), 50)]
+        );
+    }
+}
@@ -1,28 +1,73 @@
+use std::collections::HashMap;
+
+use actix_web::cookie::Cookie;
+use actix_web::cookie::SameSite;
+use actix_web::error::ErrorBadRequest;
+use actix_web::error::ErrorUnauthorized;
 use actix_web::get;
+use actix_web::http::header::ContentType;
 use actix_web::post;
 use actix_web::web;
+use actix_web::HttpRequest;
 use actix_web::HttpResponse;
 use actix_web::Responder;
+use chrono::NaiveDate;
+use chrono::NaiveTime;
 use maud::html;
 use maud::Markup;
 use serde::Deserialize;
+use urlencoding::encode;
 
+use crate::accounts::account_form;
+use crate::accounts::Account;
+use crate::accounts::ArtistList;
+use crate::accounts::SESSION_COOKIE;
+use crate::auth::LastfmAuth;
+use crate::charts::get_offline_library;
 use crate::charts::Period;
 use crate::charts::User;
+use crate::charts::DEFAULT_LIMIT;
+use crate::compare::Comparison;
+use crate::config::config;
+use crate::config::Config;
+use crate::delete_api_key;
+use crate::delete_lastfm_session;
 use crate::error_500;
+use crate::error_lastfm;
+use crate::genre_link;
 use crate::get_api_key;
+use crate::get_api_key_date;
+use crate::get_genre;
+use crate::get_lastfm_sessions;
 use crate::get_random_artist;
+use crate::get_seed_progress;
+use crate::get_top_genres;
 use crate::html;
+use crate::lastfm_key;
+use crate::profile::TagProfile;
+use crate::recommend::Recommendations;
+use crate::secret::mask;
+use crate::seed;
+use crate::seed::seed_genres;
+use crate::seed::SeedOptions;
+use crate::stats::Stats;
 use crate::store_api_key;
+use crate::store_lastfm_session;
+use crate::tag_graph::TagGraph;
+use crate::tag_graph::TagSource;
+use crate::trends::Trends;
+use crate::validate_api_key;
 use crate::ArtistTree;
+use crate::ListenerFilter;
 use crate::SqPool;
+use crate::TagFilter;
 use crate::APP_NAME;
-use crate::LASTFM_USER;
+use crate::OFFLINE;
 
 // as far as possible, this file should not contain overly complicated markup;
 // simple markup is still ok for locality of behaviour
 
-async fn redirect(path: &str) -> impl Responder {
+async fn redirect(path: &str) -> HttpResponse {
     HttpResponse::SeeOther()
         .insert_header(("Location", path))
         .finish()
@@ -34,7 +79,10 @@ pub async fn not_found() -> impl Responder {
 }
 
 #[get("/")]
-async fn home() -> actix_web::Result<Markup> {
+async fn home(
+    config: web::Data<Config>,
+    account: Option<Account>,
+) -> actix_web::Result<Markup> {
     let html = html! {
         h1 { (APP_NAME.to_string()) }
         h2 { "Home" }
@@ -42,14 +90,28 @@ async fn home() -> actix_web::Result<Markup> {
             // note the trailing slashes!
             li { (html::link("/artists/", "Artists")) }
             li { (html::link("/charts/", "Charts")) }
-            // li { (html::link("/genres", "Genres")) }
+            li { (html::link("/genres", "Genres")) }
+            @if !config.server.accounts {
+                li { (html::link("/settings", "Settings")) }
+            }
+        }
+        @if config.server.accounts {
+            @match account {
+                Some(account) => p {
+                    "Logged in as " (html::link("/account", &account.name))
+                },
+                None => p {
+                    (html::link("/account/login", "Log in"))
+                    " | "
+                    (html::link("/account/register", "Register"))
+                },
+            }
         }
         // div class="spacer" {}
         // footer {
         //     (html::link("/prefs", "Preferences"))
         // }
     };
-    // https://www.last.fm/api/show/library.getArtists
     Ok(html)
 }
 
@@ -59,31 +121,249 @@ struct ApiKeyFormData {
     redirect_to: String,
 }
 
+/// Store (or replace) the shared API key. If it is rejected, the form is shown
+/// again with the reason.
 #[post("/login")]
 async fn login(
     form: web::Form<ApiKeyFormData>,
     pool: web::Data<SqPool>,
-) -> impl Responder {
-    store_api_key(&pool, &form.0.key).await.unwrap();
-    redirect(&form.0.redirect_to).await
+) -> HttpResponse {
+    match store_api_key(&pool, &form.key).await {
+        Ok(()) => redirect(&form.redirect_to).await,
+        Err(e) => {
+            let html = html! {
+                (html::header("API key"))
+                p { b { (e) } }
+                (html::api_key_form(&form.redirect_to))
+            };
+            HttpResponse::BadRequest()
+                .content_type(ContentType::html())
+                .body(html.into_string())
+        }
+    }
+}
+
+// settings {{{
+async fn settings_html(
+    pool: &SqPool,
+    config: &Config,
+    message: Option<&str>,
+) -> actix_web::Result<Markup> {
+    let key = get_api_key(pool).await.map_err(error_500)?;
+    let date = get_api_key_date(pool).await.map_err(error_500)?;
+    let sessions = match LastfmAuth::from_config() {
+        Some(_) => Some(get_lastfm_sessions(pool, None).await.map_err(error_500)?),
+        None => None,
+    };
+    Ok(html::settings_page(
+        key.as_deref().map(mask).as_deref(),
+        date.as_deref(),
+        config.server.secret.is_some(),
+        sessions.as_deref(),
+        message,
+    ))
+}
+
+#[get("/settings")]
+async fn settings(
+    pool: web::Data<SqPool>,
+    config: web::Data<Config>,
+) -> actix_web::Result<Markup> {
+    settings_html(&pool, &config, None).await
+}
+
+/// Check that the stored key is still accepted by Last.fm (e.g. after it was
+/// revoked)
+#[post("/settings/validate")]
+async fn validate_settings(
+    pool: web::Data<SqPool>,
+    config: web::Data<Config>,
+) -> actix_web::Result<Markup> {
+    let message = match get_api_key(&pool).await.map_err(error_500)? {
+        Some(key) => match validate_api_key(&key).await {
+            Ok(()) => "API key is valid".to_string(),
+            Err(e) => e.to_string(),
+        },
+        None => "No API key stored".to_string(),
+    };
+    settings_html(&pool, &config, Some(&message)).await
+}
+
+#[post("/settings/delete")]
+async fn delete_settings_key(pool: web::Data<SqPool>) -> actix_web::Result<HttpResponse> {
+    delete_api_key(&pool).await.map_err(error_500)?;
+    Ok(redirect("/settings").await)
+}
+//}}}
+
+// lastfm auth {{{
+/// Holds the nonce passed to `/lastfm/callback`, so that a callback can only
+/// complete a flow started by the same browser
+const LASTFM_STATE_COOKIE: &str = "lasttree_lastfm_state";
+
+fn lastfm_auth() -> actix_web::Result<LastfmAuth> {
+    LastfmAuth::from_config().ok_or(ErrorBadRequest(
+        "Last.fm authentication requires lastfm.shared_secret to be set",
+    ))
+}
+
+fn lastfm_state_cookie(state: &str) -> Cookie<'static> {
+    // Lax, since the callback is a top-level redirect from Last.fm
+    Cookie::build(LASTFM_STATE_COOKIE, state.to_string())
+        .path("/lastfm/")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .finish()
+}
+
+/// Start of the web auth flow; Last.fm redirects to `/lastfm/callback`
+#[get("/lastfm/connect")]
+async fn lastfm_connect(req: HttpRequest) -> actix_web::Result<HttpResponse> {
+    let state = uuid::Uuid::new_v4().simple().to_string();
+    let info = req.connection_info();
+    let callback = format!(
+        "{}://{}/lastfm/callback?state={state}",
+        info.scheme(),
+        info.host()
+    );
+    let url = lastfm_auth()?
+        .authorize_url(None, Some(&callback))
+        .map_err(error_500)?;
+    Ok(HttpResponse::SeeOther()
+        .insert_header(("Location", url.as_str()))
+        .cookie(lastfm_state_cookie(&state))
+        .finish())
+}
+
+#[derive(Deserialize)]
+struct LastfmCallbackQuery {
+    token: String,
+    state: String,
+}
+
+/// Without accounts, everything is shared anyway (e.g. Last.fm sessions are
+/// managed via `/settings`, which is not available if accounts are enabled)
+fn require_account(
+    account: Option<&Account>,
+    config: &Config,
+) -> actix_web::Result<()> {
+    match (account, config.server.accounts) {
+        (None, true) => Err(ErrorUnauthorized("Not logged in")),
+        _ => Ok(()),
+    }
+}
+
+/// The session is attached to the logged-in account, if any. `state` must
+/// match the cookie set by `/lastfm/connect`; otherwise, anyone could attach
+/// their own Last.fm account to a victim's session (login CSRF).
+#[get("/lastfm/callback")]
+async fn lastfm_callback(
+    query: web::Query<LastfmCallbackQuery>,
+    req: HttpRequest,
+    pool: web::Data<SqPool>,
+    account: Option<Account>,
+    config: web::Data<Config>,
+) -> actix_web::Result<HttpResponse> {
+    require_account(account.as_ref(), &config)?;
+    match req.cookie(LASTFM_STATE_COOKIE) {
+        Some(c) if !query.state.is_empty() && c.value() == query.state => (),
+        _ => return Err(ErrorBadRequest("Invalid state; connect again")),
+    }
+    let session = match lastfm_auth()?.get_session(&query.token).await {
+        Ok(session) => session,
+        Err(e) => {
+            let html = html! {
+                (html::header("Last.fm account"))
+                p { "Could not connect Last.fm account: " (e) }
+                p { (html::link("/lastfm/connect", "Try again")) }
+            };
+            return Ok(HttpResponse::BadRequest()
+                .content_type(ContentType::html())
+                .body(html.into_string()));
+        }
+    };
+
+    store_lastfm_session(&pool, &session, account.as_ref().map(|a| a.id))
+        .await
+        .map_err(error_500)?;
+
+    let mut resp = match account {
+        Some(account) => {
+            account
+                .set_lastfm_user(&pool, &session.name)
+                .await
+                .map_err(error_500)?;
+            redirect("/account").await
+        }
+        None => redirect("/settings").await,
+    };
+    // the nonce is single-use
+    resp.add_removal_cookie(&lastfm_state_cookie(""))
+        .map_err(error_500)?;
+    Ok(resp)
+}
+
+#[derive(Deserialize)]
+struct LastfmDisconnectFormData {
+    name: String,
+}
+
+#[post("/lastfm/disconnect")]
+async fn lastfm_disconnect(
+    form: web::Form<LastfmDisconnectFormData>,
+    pool: web::Data<SqPool>,
+    account: Option<Account>,
+    config: web::Data<Config>,
+) -> actix_web::Result<HttpResponse> {
+    require_account(account.as_ref(), &config)?;
+    let account_id = account.as_ref().map(|a| a.id);
+    delete_lastfm_session(&pool, &form.name, account_id)
+        .await
+        .map_err(error_500)?;
+    match account {
+        Some(_) => Ok(redirect("/account").await),
+        None => Ok(redirect("/settings").await),
+    }
 }
+//}}}
 
+/// With one or more `seed` params (e.g. `/artists/?seed=A&seed=B&seed=C`), a
+/// tree is built from all seeds at once. Otherwise, a search form is shown.
 #[get("/artists/")]
-pub async fn search_artists(pool: web::Data<SqPool>) -> actix_web::Result<Markup> {
+pub async fn search_artists(
+    req: HttpRequest,
+    pool: web::Data<SqPool>,
+    account: Option<Account>,
+    config: web::Data<Config>,
+) -> actix_web::Result<Markup> {
     // https://github.com/sekunho/emojied/blob/8b08f35ab237eb1d2417e68f92f0337fc7868c1b/src/views/url.rs#L54
 
+    let query = TreeQuery::parse(&req)?;
+    if !query.seed.is_empty() {
+        let tree = ArtistTree::from_seeds(&query.seed);
+        let tree = for_account(tree, account.as_ref(), &pool)
+            .await
+            .map_err(error_500)?;
+        return query.build(tree, account.as_ref(), &pool).await;
+    }
+
     // TODO: button for random artist (htmx?)
     let _rand = get_random_artist(&pool)
         .await
         .map_err(error_500)?
         .unwrap_or("".to_owned());
 
-    let key = get_api_key(&pool).await.map_err(error_500)?;
+    let key = match account.and_then(|a| a.api_key) {
+        Some(key) => Some(key),
+        None => get_api_key(&pool).await.map_err(error_500)?,
+    };
 
     let html = html! {
         (html::header("Artists"))
         // (rand)
-        @if key.is_none() {
+        @if key.is_none() && config.server.accounts {
+            p { "No API key; set one on the " (html::link("/account", "account page")) }
+        } @else if key.is_none() {
             (html::api_key_form("/artists/"))
         } @else {
             form
@@ -126,50 +406,190 @@ async fn post_artists(form: web::Form<ArtistFormData>) -> impl Responder {
     redirect(&path).await
 }
 
+/// Optional url params for `/artists/{artist}` (and `/artists/`), e.g.
+/// `/artists/pestilence?tag=death+metal&tag=technical+death+metal&max_listeners=100000`
+/// (also used by the JSON API)
+#[derive(Deserialize)]
+pub(crate) struct TreeQuery {
+    #[serde(default)]
+    fewer_listeners: bool,
+    max_listeners: Option<u32>,
+
+    // repeated keys are not supported by `web::Query` (serde_urlencoded), hence
+    // serde_html_form
+    #[serde(default)]
+    tag: Vec<String>,
+    #[serde(default)]
+    not_tag: Vec<String>,
+
+    /// Only used by `/artists/` (and `/api/v1/trees`)
+    #[serde(default)]
+    pub(crate) seed: Vec<String>,
+
+    /// Build the tree from cached data only
+    #[serde(default)]
+    offline: bool,
+
+    /// Last.fm user whose library is used to mark known artists. Default: the
+    /// logged-in account's Last.fm user, or `lastfm.default_user`
+    user: Option<String>,
+    #[serde(default)]
+    hide_known: bool,
+}
+
+impl TreeQuery {
+    pub(crate) fn parse(req: &HttpRequest) -> actix_web::Result<Self> {
+        serde_html_form::from_str(req.query_string()).map_err(ErrorBadRequest)
+    }
+
+    fn listener_filter(&self) -> ListenerFilter {
+        ListenerFilter {
+            fewer_than_parent: self.fewer_listeners,
+            max_listeners: self.max_listeners,
+        }
+    }
+
+    fn tag_filter(&self) -> TagFilter {
+        TagFilter {
+            include: self.tag.clone(),
+            exclude: self.not_tag.clone(),
+        }
+    }
+
+    /// Library of the Last.fm user (see `user`). This is only decoration, so
+    /// any error (e.g. unknown user) just results in an empty library. In
+    /// offline mode, no requests are made (see `get_offline_library`).
+    async fn known_artists(
+        &self,
+        account: Option<&Account>,
+        pool: &SqPool,
+    ) -> HashMap<String, u64> {
+        let user = match (&self.user, account) {
+            (Some(user), _) => Some(user.clone()),
+            (None, Some(account)) => account.lastfm_user.clone(),
+            (None, None) => config().lastfm.default_user.clone(),
+        };
+        let Some(user) = user else {
+            return HashMap::new();
+        };
+        if self.offline || *OFFLINE {
+            return get_offline_library(pool, &user).await.unwrap_or_default();
+        }
+        match User::new(&user, account, pool).await {
+            Ok(user) => user.get_library(pool).await.unwrap_or_default(),
+            Err(_) => HashMap::new(),
+        }
+    }
+
+    /// Apply filters (and the user's library) to `tree`, and build it
+    pub(crate) async fn build_tree(
+        &self,
+        tree: ArtistTree,
+        account: Option<&Account>,
+        pool: &SqPool,
+    ) -> anyhow::Result<ArtistTree> {
+        tree.with_known(self.known_artists(account, pool).await)
+            .with_hide_known(self.hide_known)
+            .with_listener_filter(self.listener_filter())
+            .with_tag_filter(self.tag_filter())
+            .with_offline(self.offline)
+            .build_tree(pool)
+            .await
+    }
+
+    /// Build the tree, and render it (or the error)
+    async fn build(
+        &self,
+        tree: ArtistTree,
+        account: Option<&Account>,
+        pool: &SqPool,
+    ) -> actix_web::Result<Markup> {
+        let html = match self
+            .build_tree(tree, account, pool)
+            .await
+            .map_err(error_500)
+        {
+            Ok(tree) => tree.as_html().await.map_err(error_500)?,
+            Err(e) => html! {
+                // "Artist not found: "(artist)
+                (e)
+                p { (html::link("/artists/", "Return")) }
+            // TODO: try artist search, then show results in list
+            // https://www.last.fm/api/show/artist.search
+            },
+        };
+
+        Ok(html)
+    }
+}
+
 #[get("/artists/{artist}")]
 async fn show_artist(
     // https://actix.rs/docs/url-dispatch/#scoping-routes
-    // TODO: capture url params? (e.g. /artists/foo?key=val)
     path: web::Path<String>,
+    req: HttpRequest,
     pool: web::Data<SqPool>,
+    account: Option<Account>,
 ) -> actix_web::Result<Markup> {
     let artist = path.into_inner();
+    let query = TreeQuery::parse(&req)?;
+
+    if let Some(account) = &account {
+        account.add_history(&pool, &artist).await.map_err(error_500)?;
+    }
 
-    let html = match ArtistTree::new(&artist)
-        .build_tree(&pool)
+    let tree = for_account(ArtistTree::new(&artist), account.as_ref(), &pool)
         .await
-        .map_err(error_500)
-    {
-        Ok(tree) => tree.as_html().await.map_err(error_500)?,
-        Err(e) => html! {
-            // "Artist not found: "(artist)
-            (e)
-            p { (html::link("/artists/", "Return")) }
-        // TODO: try artist search, then show results in list
-        // https://www.last.fm/api/show/artist.search
-        },
-    };
+        .map_err(error_500)?;
+    query.build(tree, account.as_ref(), &pool).await
+}
 
-    Ok(html)
+/// Apply the logged-in account's API key and ignore list (if any)
+pub(crate) async fn for_account(
+    tree: ArtistTree,
+    account: Option<&Account>,
+    pool: &SqPool,
+) -> sqlx::Result<ArtistTree> {
+    let Some(account) = account else {
+        return Ok(tree);
+    };
+    let ignored = account.get_ignored(pool).await?;
+    Ok(tree
+        .with_key(account.api_key.clone())
+        .with_ignored(ignored)
+        .with_account_actions(true))
 }
 
 // https://www.last.fm/api/show/geo.getTopArtists
 // https://www.last.fm/api/show/user.getTopArtists
 
-// #[get("/genres")]
-// async fn genres() -> actix_web::Result<Markup> {
-//     // arguably, we don't need to cache this
-//     let genres = get_top_genres().await.map_err(error_500)?;
-//     let html = html! {
-//         (html::header("Genres"))
-//         @for g in genres.0.iter() {
-//             (html::list_item(&html::link(&g.url,
-// &g.name.to_lowercase()).into_string()))         }
-//     // TODO: hx-swap afterend? this requires us to keep track of what page we
-// are on     // https://htmx.org/attributes/hx-swap/
-//     };
-//     Ok(html)
-// }
+#[derive(Deserialize)]
+pub(crate) struct PageQuery {
+    pub(crate) page: Option<u32>,
+}
+
+#[get("/genres")]
+async fn genres(
+    query: web::Query<PageQuery>,
+    pool: web::Data<SqPool>,
+    account: Option<Account>,
+) -> actix_web::Result<Markup> {
+    // arguably, we don't need to cache this
+    let page = query.page.unwrap_or(1).max(1);
+    let key = account.and_then(|a| a.api_key);
+    let key = lastfm_key(&pool, key.as_deref()).await?;
+    let genres = get_top_genres(page, &key).await.map_err(error_500)?;
+    let html = html! {
+        (html::header("Genres"))
+        ul {
+            @for g in genres.0.iter() {
+                (html::list_item(&genre_link(&g.name.to_lowercase()).into_string()))
+            }
+        }
+        (html::pagination("/genres", page, None))
+    };
+    Ok(html)
+}
 
 // note that aside from the global chart, last.fm has no concept whatsoever of
 // "trending" tags with filters (e.g. top artists for tag X in the last n
@@ -179,25 +599,67 @@ async fn show_artist(
 //
 // for this purpose, bandcamp/spotify/discogs are better alternatives
 
-// #[get("/genres/{genre}")]
-// async fn show_genre(
-//     path: web::Path<String>,
-//     // pool: web::Data<SqPool>,
-// ) -> actix_web::Result<Markup> {
-//     let genre = path.into_inner();
-//
-//     // let html = match ArtistTree::new(&artist).await {
-//     //     Ok(tree) => tree,
-//     //     Err(_) => html! {
-//     //         "Genre not found: "(genre)
-//     //         p { (html::link("/", "Home")) }
-//     //     },
-//     // };
-//
-//     let html = html! {};
-//
-//     Ok(html)
-// }
+#[get("/genres/{genre}")]
+async fn show_genre(
+    path: web::Path<String>,
+    query: web::Query<PageQuery>,
+    pool: web::Data<SqPool>,
+    account: Option<Account>,
+) -> actix_web::Result<Markup> {
+    let genre = path.into_inner();
+    let page = query.page.unwrap_or(1).max(1);
+
+    let key = account.and_then(|a| a.api_key);
+    let key = lastfm_key(&pool, key.as_deref()).await?;
+    let html = match get_genre(&genre, page, &key).await {
+        Ok(genre) => genre.as_html(&pool).await,
+        Err(e) => html! {
+            (html::header("Genres"))
+            "Genre not found: "(genre)
+            p { (e) }
+            p { (html::link("/genres", "Return")) }
+        },
+    };
+
+    Ok(html)
+}
+
+#[derive(Deserialize)]
+struct TagGraphQuery {
+    #[serde(default)]
+    source: TagSource,
+    depth: Option<u32>,
+}
+
+/// `/genres/{genre}/graph?source=local|lastfm&depth=2`
+#[get("/genres/{genre}/graph")]
+async fn show_tag_graph(
+    path: web::Path<String>,
+    query: web::Query<TagGraphQuery>,
+    pool: web::Data<SqPool>,
+    account: Option<Account>,
+) -> actix_web::Result<Markup> {
+    let genre = path.into_inner();
+
+    let mut graph = TagGraph::new(&genre)
+        .with_source(query.source)
+        .with_key(account.and_then(|a| a.api_key));
+    if let Some(depth) = query.depth {
+        graph = graph.with_depth(depth);
+    }
+
+    let html = match graph.build(&pool).await {
+        Ok(graph) => graph.as_html(),
+        Err(e) => html! {
+            (html::header("Tag graph"))
+            "Could not build tag graph: "(genre)
+            p { (e) }
+            p { (html::link(&format!("/genres/{}", encode(&genre)), "Return")) }
+        },
+    };
+
+    Ok(html)
+}
 
 // https://github.com/GroupTheorist12/SimpleRustWebService/blob/main/cars_service/src/main.rs#L31
 #[derive(Deserialize, Debug)]
@@ -207,11 +669,29 @@ struct ChartsPath {
     // period: String,
 }
 
-/// Redirect `/charts/` -> `/charts/{default_user}`
+/// Redirect `/charts/` -> `/charts/{user}`, where `user` is the logged-in
+/// account's Last.fm user, or `lastfm.default_user`
 // https://github.com/actix/actix-web/discussions/2874#discussioncomment-3647031
 #[get("/charts/")]
-async fn get_charts_null() -> impl Responder {
-    redirect(&format!("/charts/{}/", *LASTFM_USER)).await
+async fn get_charts_null(
+    config: web::Data<Config>,
+    account: Option<Account>,
+) -> HttpResponse {
+    let user = account
+        .and_then(|a| a.lastfm_user)
+        .or(config.lastfm.default_user.clone());
+    match user {
+        Some(user) => redirect(&format!("/charts/{user}/")).await,
+        None => HttpResponse::NotFound()
+            .content_type(ContentType::html())
+            .body(
+                html! {
+                    (html::header("Charts"))
+                    p { "No default user; set lastfm.default_user (or $LASTFM_USER), or go to /charts/{user}" }
+                }
+                .into_string(),
+            ),
+    }
 }
 
 /// Redirect `/charts/{user}` -> `/charts/{user}/{period}`
@@ -220,10 +700,31 @@ async fn get_charts_user(path: web::Path<ChartsPath>) -> impl Responder {
     redirect(&format!("/charts/{}/{}", path.user, Period::default())).await
 }
 
+/// `/charts/{user}/{period}?limit=20&page=2`
+#[derive(Deserialize)]
+pub(crate) struct ChartPageQuery {
+    pub(crate) limit: Option<u32>,
+    page: Option<u32>,
+    /// Total plays of the period, passed on by "Load more" so that it is only
+    /// fetched once (see `User::get_period_plays`)
+    plays: Option<u64>,
+}
+
+impl ChartPageQuery {
+    pub(crate) fn limit(&self) -> u32 { self.limit.unwrap_or(DEFAULT_LIMIT) }
+    pub(crate) fn page(&self) -> u32 { self.page.unwrap_or(1) }
+}
+
+/// Requests made by htmx (i.e. "Load more") only receive table rows
+fn is_htmx(req: &HttpRequest) -> bool { req.headers().contains_key("HX-Request") }
+
 #[get("/charts/{user}/{period}")]
 async fn get_charts(
     path: web::Path<ChartsPath>,
+    query: web::Query<ChartPageQuery>,
+    req: HttpRequest,
     pool: web::Data<SqPool>,
+    account: Option<Account>,
 ) -> actix_web::Result<Markup> {
     let user = &path.user;
 
@@ -236,19 +737,158 @@ async fn get_charts(
 
     // let period = path.period.as_str().try_into().unwrap_or(Period::default());
 
-    let chart = User::new(user)
-        .map_err(error_500)?
-        .get_chart_period(period)
+    // `LastfmError` already implements `ResponseError`
+    let user = User::new(user, account.as_ref(), &pool).await?;
+    let plays = match (query.plays, is_htmx(&req)) {
+        (Some(plays), _) => Some(plays),
+        (None, false) => user.get_period_plays(period).await.ok(),
+        (None, true) => None,
+    };
+    let chart = user
+        .get_chart_period(period, query.limit(), query.page())
         .await
-        .map_err(error_500)?;
+        .map_err(error_500)?
+        .with_plays(plays);
 
     // println!("{:#?}", chart);
     // println!("get_charts: {}", user);
 
-    let html = chart.as_html(user, &pool).await?;
+    if is_htmx(&req) {
+        return Ok(chart.rows_html(&user, &pool).await);
+    }
+
+    // one snapshot per day, of the default first page only (see `Trends`);
+    // a shorter chart would show every artist past its end as a dropout
+    if query.page() == 1 && query.limit() == DEFAULT_LIMIT {
+        chart.store_snapshot(&pool, user.username()).await.map_err(error_500)?;
+    }
+
+    let html = chart.as_html(&user, &pool).await?;
     Ok(html)
 }
 
+/// Dates as produced by `<input type="date">`, i.e. `YYYY-MM-DD`, or raw
+/// timestamps (as used by "Load more"). Empty inputs are submitted as empty
+/// strings.
+#[derive(Deserialize)]
+pub(crate) struct ChartWindowQuery {
+    from: Option<String>,
+    to: Option<String>,
+}
+
+impl ChartWindowQuery {
+    /// `(from, to)` as timestamps
+    pub(crate) fn window(&self) -> anyhow::Result<(Option<u64>, Option<u64>)> {
+        Ok((parse_date(&self.from)?, parse_date(&self.to)?))
+    }
+}
+
+/// Parse a `YYYY-MM-DD` date into a timestamp (midnight UTC). Timestamps are
+/// passed through as is.
+fn parse_date(date: &Option<String>) -> anyhow::Result<Option<u64>> {
+    match date.as_deref() {
+        None | Some("") => Ok(None),
+        Some(ts) if ts.chars().all(|c| c.is_ascii_digit()) => Ok(Some(ts.parse()?)),
+        Some(date) => {
+            let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")?;
+            Ok(Some(date.and_time(NaiveTime::MIN).and_utc().timestamp() as u64))
+        }
+    }
+}
+
+/// `/charts/{user}/custom?from=2024-01-01&to=2024-02-01`
+// must be registered before `get_charts`, otherwise "custom" is parsed as a
+// `Period`
+#[get("/charts/{user}/custom")]
+async fn get_charts_window(
+    path: web::Path<String>,
+    query: web::Query<ChartWindowQuery>,
+    page: web::Query<ChartPageQuery>,
+    req: HttpRequest,
+    pool: web::Data<SqPool>,
+    account: Option<Account>,
+) -> actix_web::Result<Markup> {
+    let user = path.into_inner();
+
+    let user = User::new(&user, account.as_ref(), &pool).await?;
+    let (from, to) = query.window().map_err(ErrorBadRequest)?;
+    let chart = user
+        .get_chart_window(from, to, page.limit(), page.page())
+        .await
+        .map_err(error_500)?;
+
+    if is_htmx(&req) {
+        return Ok(chart.rows_html(&user, &pool).await);
+    }
+
+    chart.as_html(&user, &pool).await
+}
+
+/// `/charts/{user}/local?from=2024-01-01&to=2024-02-01`, computed from
+/// imported scrobbles (see `scrobbles`)
+// like `get_charts_window`, must be registered before `get_charts`
+#[get("/charts/{user}/local")]
+async fn get_charts_local(
+    path: web::Path<String>,
+    query: web::Query<ChartWindowQuery>,
+    page: web::Query<ChartPageQuery>,
+    req: HttpRequest,
+    pool: web::Data<SqPool>,
+    account: Option<Account>,
+) -> actix_web::Result<Markup> {
+    let user = User::new(&path.into_inner(), account.as_ref(), &pool).await?;
+    let (from, to) = query.window().map_err(ErrorBadRequest)?;
+    let chart = user
+        .get_local_chart(from, to, page.limit(), page.page(), &pool)
+        .await
+        .map_err(error_500)?;
+
+    if is_htmx(&req) {
+        return Ok(chart.rows_html(&user, &pool).await);
+    }
+
+    chart.as_html(&user, &pool).await
+}
+
+/// Computed from imported scrobbles only, so the user is not looked up on
+/// Last.fm
+#[get("/stats/{user}")]
+async fn get_stats(
+    path: web::Path<String>,
+    pool: web::Data<SqPool>,
+) -> actix_web::Result<Markup> {
+    let stats = Stats::new(&path.into_inner(), &pool)
+        .await
+        .map_err(error_500)?;
+    Ok(stats.as_html())
+}
+
+#[derive(Deserialize)]
+struct TrendsQuery {
+    period: Option<String>,
+}
+
+/// `/charts/{user}/trends?period=7day`
+// like `get_charts_window`, must be registered before `get_charts`
+#[get("/charts/{user}/trends")]
+async fn get_trends(
+    path: web::Path<String>,
+    query: web::Query<TrendsQuery>,
+    pool: web::Data<SqPool>,
+    account: Option<Account>,
+) -> actix_web::Result<Markup> {
+    let user = User::new(&path.into_inner(), account.as_ref(), &pool).await?;
+    let period = match &query.period {
+        Some(s) => s.as_str().try_into().unwrap_or(Period::default()),
+        None => Period::default(),
+    };
+
+    let trends = Trends::new(user.username(), period, &pool)
+        .await
+        .map_err(error_500)?;
+    Ok(trends.as_html())
+}
+
 #[derive(Deserialize)]
 struct ChartFormData {
     user: String,
@@ -260,9 +900,333 @@ async fn post_charts(form: web::Form<ChartFormData>) -> impl Responder {
     redirect(&path).await
 }
 
+#[get("/charts/{user}/{period}/tags")]
+async fn get_chart_tags(
+    path: web::Path<ChartsPath>,
+    query: web::Query<ChartPageQuery>,
+    pool: web::Data<SqPool>,
+    account: Option<Account>,
+) -> actix_web::Result<Markup> {
+    let period = match &path.period {
+        Some(s) => s.as_str().try_into().unwrap_or(Period::default()),
+        None => Period::default(),
+    };
+
+    // a profile of 10 artists is not very meaningful
+    let limit = query.limit.unwrap_or(50);
+    let profile = TagProfile::new(&path.user, period, limit, account.as_ref(), &pool)
+        .await
+        .map_err(error_lastfm)?;
+
+    Ok(profile.as_html())
+}
+
+#[get("/recommend/{user}/{period}")]
+async fn get_recommendations(
+    path: web::Path<ChartsPath>,
+    query: web::Query<ChartPageQuery>,
+    pool: web::Data<SqPool>,
+    account: Option<Account>,
+) -> actix_web::Result<Markup> {
+    let period = match &path.period {
+        Some(s) => s.as_str().try_into().unwrap_or(Period::default()),
+        None => Period::default(),
+    };
+
+    let recs = Recommendations::new(&path.user, period, query.limit(), account.as_ref(), &pool)
+        .await
+        .map_err(error_lastfm)?;
+
+    Ok(recs.as_html())
+}
+
+#[derive(Deserialize)]
+struct ComparePath {
+    user_a: String,
+    user_b: String,
+    period: String,
+}
+
+#[get("/compare/{user_a}/{user_b}/{period}")]
+async fn get_comparison(
+    path: web::Path<ComparePath>,
+    pool: web::Data<SqPool>,
+    account: Option<Account>,
+) -> actix_web::Result<Markup> {
+    let period = path.period.as_str().try_into().unwrap_or(Period::default());
+
+    let comparison = Comparison::new(&path.user_a, &path.user_b, period, account.as_ref(), &pool)
+        .await
+        .map_err(error_lastfm)?;
+
+    Ok(comparison.as_html())
+}
+
+// accounts {{{
+#[derive(Deserialize)]
+struct AccountFormData {
+    name: String,
+    password: String,
+}
+
+/// Redirect to `path`, setting the session cookie
+fn redirect_with_session(
+    path: &str,
+    token: &str,
+) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header(("Location", path))
+        .cookie(Account::session_cookie(token))
+        .finish()
+}
+
+#[get("/account/register")]
+async fn register_form() -> Markup { account_form("Register", "/account/register", None) }
+
+#[post("/account/register")]
+async fn register(
+    form: web::Form<AccountFormData>,
+    pool: web::Data<SqPool>,
+) -> actix_web::Result<HttpResponse> {
+    let result = match Account::register(&pool, &form.name, &form.password).await {
+        Ok(_) => Account::login(&pool, &form.name, &form.password).await,
+        Err(e) => Err(e),
+    };
+    let resp = match result {
+        Ok(token) => redirect_with_session("/account", &token),
+        Err(e) => HttpResponse::BadRequest()
+            .content_type(ContentType::html())
+            .body(account_form("Register", "/account/register", Some(&e.to_string())).into_string()),
+    };
+    Ok(resp)
+}
+
+#[get("/account/login")]
+async fn login_form() -> Markup { account_form("Log in", "/account/login", None) }
+
+#[post("/account/login")]
+async fn account_login(
+    form: web::Form<AccountFormData>,
+    pool: web::Data<SqPool>,
+) -> actix_web::Result<HttpResponse> {
+    let resp = match Account::login(&pool, &form.name, &form.password).await {
+        Ok(token) => redirect_with_session("/account", &token),
+        Err(e) => HttpResponse::Unauthorized()
+            .content_type(ContentType::html())
+            .body(account_form("Log in", "/account/login", Some(&e.to_string())).into_string()),
+    };
+    Ok(resp)
+}
+
+#[post("/account/logout")]
+async fn logout(
+    req: HttpRequest,
+    pool: web::Data<SqPool>,
+) -> actix_web::Result<HttpResponse> {
+    if let Some(cookie) = req.cookie(SESSION_COOKIE) {
+        Account::delete_session(&pool, cookie.value())
+            .await
+            .map_err(error_500)?;
+    }
+    let mut cookie = Account::session_cookie("");
+    cookie.make_removal();
+    Ok(HttpResponse::SeeOther()
+        .insert_header(("Location", "/"))
+        .cookie(cookie)
+        .finish())
+}
+
+#[get("/account")]
+async fn show_account(
+    account: Option<Account>,
+    pool: web::Data<SqPool>,
+) -> actix_web::Result<HttpResponse> {
+    let Some(account) = account else {
+        return Ok(redirect("/account/login").await);
+    };
+    let html = account.as_html(&pool, None).await.map_err(error_500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(html.into_string()))
+}
+
+#[derive(Deserialize)]
+struct AccountSettingsFormData {
+    lastfm_user: String,
+    /// Empty: keep the current key
+    api_key: String,
+    #[serde(default)]
+    delete_key: bool,
+}
+
+#[post("/account")]
+async fn post_account(
+    account: Account,
+    form: web::Form<AccountSettingsFormData>,
+    pool: web::Data<SqPool>,
+) -> actix_web::Result<HttpResponse> {
+    account
+        .set_lastfm_user(&pool, &form.lastfm_user)
+        .await
+        .map_err(error_500)?;
+
+    let key = form.api_key.trim();
+    if form.delete_key {
+        account.set_api_key(&pool, None).await.map_err(error_500)?;
+    } else if !key.is_empty() {
+        if let Err(e) = validate_api_key(key).await {
+            let html = account
+                .as_html(&pool, Some(&e.to_string()))
+                .await
+                .map_err(error_500)?;
+            return Ok(HttpResponse::BadRequest()
+                .content_type(ContentType::html())
+                .body(html.into_string()));
+        }
+        account
+            .set_api_key(&pool, Some(key))
+            .await
+            .map_err(error_500)?;
+    }
+
+    Ok(redirect("/account").await)
+}
+
+#[derive(Deserialize)]
+struct AccountArtistPath {
+    list: String,
+    artist: String,
+}
+
+impl AccountArtistPath {
+    fn list(&self) -> actix_web::Result<ArtistList> {
+        ArtistList::try_from(self.list.as_str()).map_err(ErrorBadRequest)
+    }
+}
+
+/// Used by the buttons in the tree table (htmx); the button is replaced by the
+/// response
+#[post("/account/{list}/{artist}")]
+async fn add_account_artist(
+    account: Account,
+    path: web::Path<AccountArtistPath>,
+    pool: web::Data<SqPool>,
+) -> actix_web::Result<Markup> {
+    let list = path.list()?;
+    account
+        .add_artist(&pool, list, &path.artist)
+        .await
+        .map_err(error_500)?;
+    let html = match list {
+        ArtistList::Favourites => html! { "Favourited" },
+        ArtistList::Ignored => html! { "Ignored" },
+    };
+    Ok(html)
+}
+
+#[post("/account/{list}/{artist}/remove")]
+async fn remove_account_artist(
+    account: Account,
+    path: web::Path<AccountArtistPath>,
+    pool: web::Data<SqPool>,
+) -> actix_web::Result<HttpResponse> {
+    account
+        .remove_artist(&pool, path.list()?, &path.artist)
+        .await
+        .map_err(error_500)?;
+    Ok(redirect("/account").await)
+}
+//}}}
+
+/// Seeding runs on the shared key, so if accounts are enabled, only logged-in
+/// users may see or start jobs
+#[get("/admin/seed")]
+async fn admin_seed(
+    pool: web::Data<SqPool>,
+    account: Option<Account>,
+    config: web::Data<Config>,
+) -> actix_web::Result<Markup> {
+    require_account(account.as_ref(), &config)?;
+    let progress = get_seed_progress(&pool).await.map_err(error_500)?;
+    let defaults = SeedOptions::default();
+
+    let html = html! {
+        (html::header("Seed"))
+        p {
+            "Cache the top artists of one or more genres (and their similar artists, "
+            "up to the given depth). Interrupted jobs can be resumed by submitting "
+            "without any genres."
+        }
+        @if seed::is_running() {
+            p { b { "A job is currently running." } " Refresh to update progress." }
+        }
+        ul {
+            @for (status, count) in progress.iter() {
+                li { (status) ": " (count) }
+            }
+        }
+        form method="POST" action="/admin/seed" {
+            label { "Genres (comma-separated): "
+                input type="text" name="tags" {}
+            }
+            " "
+            label { "Artists per genre: "
+                input type="number" name="limit" min="1" value=(defaults.limit) {}
+            }
+            " "
+            label { "Depth: "
+                input type="number" name="depth" min="0" max=(seed::MAX_DEPTH) value=(defaults.depth) {}
+            }
+            " "
+            button type="submit" { "Seed" }
+        }
+    };
+    Ok(html)
+}
+
+#[derive(Deserialize)]
+struct SeedFormData {
+    tags: String,
+    limit: u32,
+    depth: u8,
+}
+
+/// The job runs in the background; progress is shown on `/admin/seed`
+#[post("/admin/seed")]
+async fn post_admin_seed(
+    form: web::Form<SeedFormData>,
+    pool: web::Data<SqPool>,
+    account: Option<Account>,
+    config: web::Data<Config>,
+) -> actix_web::Result<HttpResponse> {
+    require_account(account.as_ref(), &config)?;
+    let opts = SeedOptions {
+        tags: form
+            .tags
+            .split(',')
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty())
+            .collect(),
+        limit: form.limit,
+        depth: form.depth,
+    };
+
+    let pool = pool.get_ref().clone();
+    actix_web::rt::spawn(async move {
+        if let Err(e) = seed_genres(&pool, &opts).await {
+            eprintln!("Seeding failed: {e}");
+        }
+    });
+
+    Ok(redirect("/admin/seed").await)
+}
+
 /// No request body is required.
 #[post("/youtube/{query}")]
-async fn search_youtube(path: web::Path<String>) -> actix_web::Result<Markup> {
+async fn search_youtube(
+    path: web::Path<String>,
+    config: web::Data<Config>,
+) -> actix_web::Result<Markup> {
     let query = path.into_inner();
 
     // yt embed would be the simplest option, but it is not very useful, unless i
@@ -275,7 +1239,7 @@ async fn search_youtube(path: web::Path<String>) -> actix_web::Result<Markup> {
     let html = match crate::player::search_youtube(&query).await {
         Ok(audio) => html! {
             p {}
-            audio controls autoplay
+            audio controls autoplay[config.player.autoplay]
                 { source src=(audio.link) { } }
             p { (audio.title) }
         },
@@ -294,41 +1258,65 @@ async fn search_youtube(path: web::Path<String>) -> actix_web::Result<Markup> {
 
 #[cfg(test)]
 mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use actix_web::web;
     use wiremock::matchers::method;
     use wiremock::matchers::path;
     use wiremock::Mock;
     use wiremock::MockServer;
     use wiremock::ResponseTemplate;
 
-    use crate::init_server;
+    use crate::config::Config;
+    use crate::init_app;
     use crate::tests::TestPool;
 
-    #[tokio::test]
+    #[actix_web::test]
     async fn show_artist() {
-        let db_url = &TestPool::new(None).await.path;
-        let port = 2020;
-        let server = init_server(db_url, port).unwrap();
+        let pool = TestPool::new(None).await;
+        let app = init_app(
+            web::Data::new(pool.pool.clone()),
+            web::Data::new(Config::default()),
+        );
+        let app = test::init_service(app).await;
 
-        // don't await the server, otherwise it will listen for incoming requests
-        // indefinitely -- i.e., like a real server! instead, put it in a tokio thread,
-        // which (somehow) terminates the server after the end of the scope
-        tokio::spawn(server);
+        let req = test::TestRequest::get().uri("/artists/loona").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
 
-        let _ = Mock::given(method("GET"))
-            .and(path("/artists/loona"))
-            .respond_with(ResponseTemplate::new(200))
-            .expect(1);
+        let body = test::read_body(resp).await;
+        assert!(String::from_utf8_lossy(&body).contains("No API key"));
+    }
 
-        // TODO: for ergonomics, we should wrap both TestPool and Server into a single
-        // struct
-        let addr = format!("http://localhost:{port}");
+    #[actix_web::test]
+    async fn shared_settings_with_accounts() {
+        let pool = TestPool::new(None).await;
+        let mut config = Config::default();
+        config.server.accounts = true;
+        let app = init_app(web::Data::new(pool.pool.clone()), web::Data::new(config));
+        let app = test::init_service(app).await;
 
-        let resp = reqwest::get(format!("{}/artists/loona", addr))
-            .await
-            .unwrap();
+        // not registered, so redirected home
+        let req = test::TestRequest::get().uri("/settings").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get("Location").unwrap(), "/");
+        let req = test::TestRequest::post().uri("/settings/delete").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get("Location").unwrap(), "/");
 
-        assert_eq!(resp.status(), 200);
-        assert!(resp.text().await.unwrap().contains("No API key"));
+        let req = test::TestRequest::post()
+            .uri("/lastfm/disconnect")
+            .set_form([("name", "foo")])
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let req = test::TestRequest::post()
+            .uri("/admin/seed")
+            .set_form([("tags", "rock"), ("limit", "1"), ("depth", "1")])
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
     }
 
     #[tokio::test]
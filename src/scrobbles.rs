@@ -0,0 +1,338 @@
+//! Module for importing scrobble history into the `scrobbles` table, from which
+//! charts for arbitrary windows can be computed locally (see
+//! `User::get_local_chart`). Sources:
+//!
+//! - CSV exports, either with a header (e.g. `uts,utc_time,artist,...,track`),
+//!   or without one, in which case the columns are assumed to be `artist,
+//!   album, track, date` (the format of the widely used lastfm-to-csv tool)
+//! - JSON exports, either a list of `user.getRecentTracks` responses (or
+//!   pages), or a flat list of objects with `artist`, `track` and a timestamp
+//! - `user.getRecentTracks` itself (see `User::import_recent_tracks`)
+
+use chrono::DateTime;
+use chrono::NaiveDateTime;
+use chrono::Utc;
+use serde_json::Value;
+
+use crate::charts::User;
+use crate::get_latest_scrobble;
+use crate::store_scrobbles;
+use crate::utils::fetch;
+use crate::SqPool;
+use crate::OFFLINE;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scrobble {
+    pub artist: String,
+    pub track: String,
+    pub album: Option<String>,
+    /// Unix time
+    pub timestamp: i64,
+}
+
+/// Unix time (in seconds or milliseconds), or one of several common date
+/// formats (assumed to be UTC)
+fn parse_timestamp(s: &str) -> Option<i64> {
+    let s = s.trim();
+    if !s.is_empty() && s.chars().all(|c| c.is_ascii_digit()) {
+        let ts: i64 = s.parse().ok()?;
+        // milliseconds
+        return Some(if ts > 100_000_000_000 { ts / 1000 } else { ts });
+    }
+
+    if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
+        return Some(dt.timestamp());
+    }
+
+    [
+        "%d %b %Y %H:%M",
+        "%d %b %Y, %H:%M",
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%d %H:%M",
+    ]
+    .iter()
+    .find_map(|fmt| NaiveDateTime::parse_from_str(s, fmt).ok())
+    .map(|dt| dt.and_utc().timestamp())
+}
+
+/// Rows with a missing artist, track or (parseable) time are skipped
+pub fn parse_csv(text: &str) -> anyhow::Result<Vec<Scrobble>> {
+    let records = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(text.as_bytes())
+        .into_records()
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let Some(first) = records.first() else {
+        return Ok(vec![]);
+    };
+    let header: Vec<String> = first.iter().map(|h| h.trim().to_lowercase()).collect();
+    let has_header = header.iter().any(|h| h == "artist" || h == "artist_name");
+
+    let col = |names: &[&str]| names.iter().find_map(|n| header.iter().position(|h| h == n));
+    let (artist, album, track, time) = match has_header {
+        true => (
+            col(&["artist", "artist_name"]),
+            col(&["album", "album_name"]),
+            col(&["track", "track_name", "name", "title"]),
+            col(&["uts", "timestamp", "date", "utc_time", "time"]),
+        ),
+        false => (Some(0), Some(1), Some(2), Some(3)),
+    };
+    let (Some(artist), Some(track), Some(time)) = (artist, track, time) else {
+        anyhow::bail!("CSV header must contain artist, track and time columns");
+    };
+
+    let mut scrobbles = vec![];
+    for record in records.iter().skip(has_header as usize) {
+        let field = |i: usize| record.get(i).map(|s| s.trim()).filter(|s| !s.is_empty());
+        let (Some(a), Some(t), Some(ts)) = (
+            field(artist),
+            field(track),
+            field(time).and_then(parse_timestamp),
+        ) else {
+            continue;
+        };
+        scrobbles.push(Scrobble {
+            artist: a.to_string(),
+            track: t.to_string(),
+            album: album.and_then(field).map(|s| s.to_string()),
+            timestamp: ts,
+        });
+    }
+    Ok(scrobbles)
+}
+
+/// `"foo"` or `{"#text": "foo"}` (or `{"name": "foo"}`, as returned with
+/// `extended=1`)
+fn text(v: &Value) -> Option<&str> {
+    v.as_str()
+        .or(v["#text"].as_str())
+        .or(v["name"].as_str())
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+}
+
+fn scrobble_from_value(v: &Value) -> Option<Scrobble> {
+    // the currently playing track has no date
+    if v["@attr"]["nowplaying"].as_str() == Some("true") {
+        return None;
+    }
+    let timestamp = [&v["date"]["uts"], &v["uts"], &v["timestamp"], &v["date"]]
+        .into_iter()
+        .find_map(|t| match t {
+            Value::Number(n) => n.as_i64(),
+            Value::String(s) => parse_timestamp(s),
+            _ => None,
+        })?;
+    Some(Scrobble {
+        artist: text(&v["artist"])?.to_string(),
+        track: v["name"]
+            .as_str()
+            .or(text(&v["track"]))
+            .or(text(&v["title"]))?
+            .to_string(),
+        album: text(&v["album"]).map(|s| s.to_string()),
+        timestamp,
+    })
+}
+
+/// Tracks in a `user.getRecentTracks` response, a page of one (`{"track":
+/// [...]}`), a list of either, or a flat list of tracks
+fn collect_tracks<'a>(
+    v: &'a Value,
+    out: &mut Vec<&'a Value>,
+) {
+    match v {
+        Value::Array(arr) => arr.iter().for_each(|x| collect_tracks(x, out)),
+        Value::Object(obj) if obj.contains_key("recenttracks") => {
+            collect_tracks(&v["recenttracks"], out)
+        }
+        Value::Object(_) if v["track"].is_array() => collect_tracks(&v["track"], out),
+        Value::Object(_) => out.push(v),
+        _ => (),
+    }
+}
+
+/// Malformed entries (and the currently playing track) are skipped
+fn scrobbles_from_value(json: &Value) -> Vec<Scrobble> {
+    let mut tracks = vec![];
+    collect_tracks(json, &mut tracks);
+    tracks.into_iter().filter_map(scrobble_from_value).collect()
+}
+
+pub fn parse_json(text: &str) -> anyhow::Result<Vec<Scrobble>> {
+    let json: Value = serde_json::from_str(text)?;
+    Ok(scrobbles_from_value(&json))
+}
+
+/// Dispatch on the first non-whitespace character
+pub fn parse_export(text: &str) -> anyhow::Result<Vec<Scrobble>> {
+    match text.trim_start().chars().next() {
+        Some('[' | '{') => parse_json(text),
+        _ => parse_csv(text),
+    }
+}
+
+impl User {
+    /// Fetch all scrobbles newer than the most recent imported one (or all
+    /// scrobbles, on the first import), 200 per request. Returns the number of
+    /// new scrobbles.
+    ///
+    /// Pages are newest first, but are stored oldest first, so that an
+    /// interrupted import is resumed (from the most recent stored scrobble)
+    /// without leaving a gap. `to` is fixed, so that scrobbles made during the
+    /// import do not shift the pages.
+    ///
+    /// https://www.last.fm/api/show/user.getRecentTracks
+    pub async fn import_recent_tracks(
+        &self,
+        pool: &SqPool,
+    ) -> anyhow::Result<u64> {
+        anyhow::ensure!(!*OFFLINE, "Cannot fetch scrobbles in offline mode");
+
+        let from = get_latest_scrobble(pool, self.username())
+            .await?
+            .map(|ts| (ts + 1).to_string());
+        let to = Utc::now().timestamp().to_string();
+
+        // only needed for the number of pages; stored last
+        let first = self.get_recent_tracks(1, from.as_deref(), &to).await?;
+        let total_pages: u32 = first["recenttracks"]["@attr"]["totalPages"]
+            .as_str()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(1);
+
+        let mut n = 0;
+        for page in (2..=total_pages).rev() {
+            let json = self.get_recent_tracks(page, from.as_deref(), &to).await?;
+            n += store_scrobbles(pool, self.username(), &scrobbles_from_value(&json)).await?;
+        }
+        n += store_scrobbles(pool, self.username(), &scrobbles_from_value(&first)).await?;
+        Ok(n)
+    }
+
+    async fn get_recent_tracks(
+        &self,
+        page: u32,
+        from: Option<&str>,
+        to: &str,
+    ) -> anyhow::Result<Value> {
+        let page = page.to_string();
+        let mut params = vec![
+            ("user", self.username()),
+            ("limit", "200"),
+            ("page", &page),
+            ("to", to),
+        ];
+        if let Some(from) = from {
+            params.push(("from", from));
+        }
+        let url = self.url("user.getrecenttracks", &params)?;
+
+        let json: Value = serde_json::from_str(&fetch(url).await?)?;
+        if let Some(msg) = json["message"].as_str() {
+            anyhow::bail!("Could not fetch scrobbles: {msg}");
+        }
+        Ok(json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_csv;
+    use super::parse_export;
+    use super::parse_timestamp;
+    use super::Scrobble;
+    use crate::get_scrobble_counts;
+    use crate::store_scrobbles;
+    use crate::tests::TestPool;
+
+    fn scrobble(
+        artist: &str,
+        track: &str,
+        album: Option<&str>,
+        timestamp: i64,
+    ) -> Scrobble {
+        Scrobble {
+            artist: artist.to_string(),
+            track: track.to_string(),
+            album: album.map(|s| s.to_string()),
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(parse_timestamp("1704067200"), Some(1704067200));
+        assert_eq!(parse_timestamp("1704067200000"), Some(1704067200));
+        assert_eq!(parse_timestamp("01 Jan 2024 00:00"), Some(1704067200));
+        assert_eq!(parse_timestamp("01 Jan 2024, 00:00"), Some(1704067200));
+        assert_eq!(parse_timestamp("2024-01-01T00:00:00Z"), Some(1704067200));
+        assert_eq!(parse_timestamp("yesterday"), None);
+    }
+
+    #[test]
+    fn csv() {
+        // lastfm-to-csv: no header
+        let text = "Loona,[+ +],\"Butterfly, Remix\",01 Jan 2024 00:00\n\
+                    Metallica,,One,01 Jan 2024 00:05\n\
+                    Broken,,,\n";
+        assert_eq!(
+            parse_csv(text).unwrap(),
+            vec![
+                scrobble("Loona", "Butterfly, Remix", Some("[+ +]"), 1704067200),
+                scrobble("Metallica", "One", None, 1704067500),
+            ]
+        );
+
+        let text = "uts,utc_time,artist,artist_mbid,album,album_mbid,track,track_mbid\n\
+                    1704067200,\"01 Jan 2024, 00:00\",Loona,,[+ +],,Butterfly,\n";
+        assert_eq!(
+            parse_export(text).unwrap(),
+            vec![scrobble("Loona", "Butterfly", Some("[+ +]"), 1704067200)]
+        );
+    }
+
+    #[test]
+    fn json() {
+        let text = r##"[{"recenttracks": {"track": [
+            {"artist": {"#text": "Loona"}, "name": "Butterfly", "album": {"#text": ""},
+             "@attr": {"nowplaying": "true"}},
+            {"artist": {"#text": "Loona"}, "name": "Butterfly", "album": {"#text": "[+ +]"},
+             "date": {"uts": "1704067200", "#text": "01 Jan 2024, 00:00"}}
+        ]}}]"##;
+        assert_eq!(
+            parse_export(text).unwrap(),
+            vec![scrobble("Loona", "Butterfly", Some("[+ +]"), 1704067200)]
+        );
+
+        let text = r#"[{"artist": "Metallica", "track": "One", "timestamp": 1704067500}]"#;
+        assert_eq!(
+            parse_export(text).unwrap(),
+            vec![scrobble("Metallica", "One", None, 1704067500)]
+        );
+    }
+
+    #[tokio::test]
+    async fn local_counts() {
+        let pool = &TestPool::new(None).await.pool;
+        let scrobbles = vec![
+            scrobble("Loona", "Butterfly", None, 100),
+            scrobble("LOONA", "Hi High", None, 200),
+            scrobble("Metallica", "One", None, 300),
+            scrobble("Metallica", "One", None, 1000),
+        ];
+        assert_eq!(store_scrobbles(pool, "Foo", &scrobbles).await.unwrap(), 4);
+        // idempotent
+        assert_eq!(store_scrobbles(pool, "foo", &scrobbles).await.unwrap(), 0);
+
+        let counts = get_scrobble_counts(pool, "foo", 0, 500).await.unwrap();
+        assert_eq!(
+            counts,
+            vec![("LOONA".to_string(), 2), ("Metallica".to_string(), 1)]
+        );
+    }
+}
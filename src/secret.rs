@@ -0,0 +1,105 @@
+//! Module for encrypting Last.fm API keys at rest, with a key derived from
+//! `server.secret` ($LASTTREE_SECRET). Stored values are prefixed with
+//! `PREFIX`; anything else is treated as a legacy plaintext key, which is
+//! encrypted at the next startup (see `encrypt_stored_keys`).
+//!
+//! If no secret is set, keys are stored in plaintext, as before.
+
+use base64::Engine;
+use chacha20poly1305::aead::Aead;
+use chacha20poly1305::aead::AeadCore;
+use chacha20poly1305::aead::KeyInit;
+use chacha20poly1305::aead::OsRng;
+use chacha20poly1305::ChaCha20Poly1305;
+use chacha20poly1305::Nonce;
+use sha2::Digest;
+use sha2::Sha256;
+
+use crate::config::config;
+use crate::BASE64;
+
+const PREFIX: &str = "enc:v1:";
+const NONCE_LEN: usize = 12;
+
+fn cipher(secret: &str) -> ChaCha20Poly1305 { ChaCha20Poly1305::new(&Sha256::digest(secret.as_bytes())) }
+
+pub fn is_encrypted(stored: &str) -> bool { stored.starts_with(PREFIX) }
+
+/// Returns `plain` unchanged if `secret` is `None`
+pub fn encrypt_with(
+    secret: Option<&str>,
+    plain: &str,
+) -> anyhow::Result<String> {
+    let Some(secret) = secret else {
+        return Ok(plain.to_string());
+    };
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let mut bytes = cipher(secret)
+        .encrypt(&nonce, plain.as_bytes())
+        .map_err(|_| anyhow::anyhow!("Could not encrypt API key"))?;
+    bytes.splice(0..0, nonce);
+    Ok(format!("{PREFIX}{}", BASE64.encode(bytes)))
+}
+
+/// Plaintext values are returned unchanged
+pub fn decrypt_with(
+    secret: Option<&str>,
+    stored: &str,
+) -> anyhow::Result<String> {
+    let Some(encoded) = stored.strip_prefix(PREFIX) else {
+        return Ok(stored.to_string());
+    };
+    let secret = secret.ok_or(anyhow::anyhow!(
+        "API key is encrypted, but server.secret is not set"
+    ))?;
+    let bytes = BASE64.decode(encoded)?;
+    if bytes.len() < NONCE_LEN {
+        anyhow::bail!("Encrypted API key is truncated");
+    }
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+    let plain = cipher(secret)
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow::anyhow!("Could not decrypt API key (was server.secret changed?)"))?;
+    Ok(String::from_utf8(plain)?)
+}
+
+pub fn encrypt(plain: &str) -> anyhow::Result<String> { encrypt_with(config().server.secret.as_deref(), plain) }
+
+pub fn decrypt(stored: &str) -> anyhow::Result<String> { decrypt_with(config().server.secret.as_deref(), stored) }
+
+/// Only the last 4 characters are shown
+pub fn mask(key: &str) -> String {
+    let n = key.chars().count();
+    let tail: String = key.chars().skip(n.saturating_sub(4)).collect();
+    format!("{}{tail}", "•".repeat(n.saturating_sub(4)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decrypt_with;
+    use super::encrypt_with;
+    use super::is_encrypted;
+    use super::mask;
+
+    #[test]
+    fn roundtrip() {
+        let key = "0123456789abcdef0123456789abcdef";
+
+        let stored = encrypt_with(Some("hunter2"), key).unwrap();
+        assert!(is_encrypted(&stored));
+        assert!(!stored.contains(key));
+        // random nonce
+        assert_ne!(stored, encrypt_with(Some("hunter2"), key).unwrap());
+
+        assert_eq!(decrypt_with(Some("hunter2"), &stored).unwrap(), key);
+        assert!(decrypt_with(Some("hunter3"), &stored).is_err());
+        assert!(decrypt_with(None, &stored).is_err());
+
+        // legacy plaintext
+        assert_eq!(encrypt_with(None, key).unwrap(), key);
+        assert_eq!(decrypt_with(Some("hunter2"), key).unwrap(), key);
+
+        assert_eq!(mask(key), format!("{}cdef", "•".repeat(28)));
+        assert_eq!(mask("abc"), "abc");
+    }
+}
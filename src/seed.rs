@@ -0,0 +1,166 @@
+//! Module for seeding the db with the most popular artists of one or more
+//! genres (and, optionally, their similar artists), so that trees and random
+//! artists are instant for those genres.
+//!
+//! Artists are processed via the `seed_queue` table, so an interrupted job can
+//! be resumed simply by running it again.
+
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+use crate::artists::Artist;
+use crate::enqueue_seed;
+use crate::get_genre_artists;
+use crate::lastfm_key;
+use crate::next_seed;
+use crate::set_seed_status;
+use crate::ArtistTree;
+use crate::SeedItem;
+use crate::SqPool;
+
+/// Minimum similarity for a similar artist to be queued; same as the default
+/// `ArtistTree` threshold
+const THRESHOLD: i64 = 70;
+
+/// Max expansion depth, since the number of artists grows exponentially with
+/// depth
+pub const MAX_DEPTH: u8 = 3;
+
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Resets `RUNNING`, even if the job panics
+struct RunGuard;
+
+impl Drop for RunGuard {
+    fn drop(&mut self) { RUNNING.store(false, Ordering::SeqCst); }
+}
+
+pub fn is_running() -> bool { RUNNING.load(Ordering::SeqCst) }
+
+pub struct SeedOptions {
+    pub tags: Vec<String>,
+
+    /// Number of top artists per tag. Default: 25
+    pub limit: u32,
+
+    /// 0 only seeds the top artists themselves, 1 also seeds their similar
+    /// artists, and so on. Clamped to `MAX_DEPTH`. Default: 1
+    pub depth: u8,
+}
+
+impl Default for SeedOptions {
+    fn default() -> Self {
+        Self {
+            tags: vec![],
+            limit: 25,
+            depth: 1,
+        }
+    }
+}
+
+/// Queue the top artists of each tag, then process the queue. Returns the
+/// number of artists processed.
+pub async fn seed_genres(
+    pool: &SqPool,
+    opts: &SeedOptions,
+) -> anyhow::Result<usize> {
+    let key = lastfm_key(pool, None).await?;
+    let depth = opts.depth.min(MAX_DEPTH);
+    for tag in opts.tags.iter() {
+        for artist in get_genre_artists(tag, opts.limit, &key).await? {
+            enqueue_seed(pool, &artist, tag, 0, depth.into()).await?;
+        }
+    }
+    run_seed(pool).await
+}
+
+/// Process all pending artists (including those left over from an interrupted
+/// job), each up to the depth of the job that queued it. Only one job can run
+/// at a time.
+///
+/// All requests go through the global rate limiter, so this is slow by design.
+pub async fn run_seed(pool: &SqPool) -> anyhow::Result<usize> {
+    if RUNNING.swap(true, Ordering::SeqCst) {
+        anyhow::bail!("A seeding job is already running");
+    }
+    let _guard = RunGuard;
+
+    let mut processed = 0;
+    while let Some(item) = next_seed(pool).await? {
+        // one bad artist should not abort the whole job
+        let status = match seed_artist(pool, &item).await {
+            Ok(()) => "done",
+            Err(e) => {
+                eprintln!("Failed to seed {}: {e}", item.artist);
+                "failed"
+            }
+        };
+        set_seed_status(pool, &item.artist, status).await?;
+        processed += 1;
+    }
+
+    Ok(processed)
+}
+
+/// Build (and thus cache) the tree of `artist`, then cache listeners and tags
+/// of every node. Unlike `seed_genres`, this does not use the queue. Returns
+/// the number of artists processed.
+pub async fn warm_artist(
+    pool: &SqPool,
+    artist: &str,
+) -> anyhow::Result<usize> {
+    let tree = ArtistTree::new(artist).build_tree(pool).await?;
+    for node in tree.nodes() {
+        let artist = Artist::new(node);
+        artist.get_listeners(pool).await?;
+        artist.get_tags(pool).await?;
+    }
+    Ok(tree.nodes().count())
+}
+
+/// Cache similar artists, listeners and tags
+async fn seed_artist(
+    pool: &SqPool,
+    item: &SeedItem,
+) -> anyhow::Result<()> {
+    let artist = Artist::new(&item.artist);
+    let similars = artist.get_similar_artists(pool).await?;
+    artist.get_listeners(pool).await?;
+    artist.get_tags(pool).await?;
+
+    if item.depth < item.max_depth {
+        for (child, _) in similars.iter().filter(|x| *x.1 >= THRESHOLD) {
+            enqueue_seed(pool, child, &item.tag, item.depth + 1, item.max_depth).await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::seed_genres;
+    use super::SeedOptions;
+    use crate::get_seed_progress;
+    use crate::tests::TestPool;
+    use crate::LASTFM_KEY;
+
+    #[tokio::test]
+    async fn seed_top_artists() {
+        let pool = &TestPool::new(Some(&LASTFM_KEY)).await.pool;
+        let opts = SeedOptions {
+            tags: vec!["k-pop".to_string()],
+            limit: 2,
+            depth: 0,
+        };
+
+        assert_eq!(seed_genres(pool, &opts).await.unwrap(), 2);
+        assert_eq!(
+            get_seed_progress(pool).await.unwrap(),
+            [("done".to_string(), 2)]
+        );
+
+        // nothing left to resume
+        assert_eq!(seed_genres(pool, &opts).await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,440 @@
+//! Module for listening statistics computed from imported scrobbles (see
+//! `scrobbles`). Nothing is fetched from Last.fm, so this also works offline.
+//!
+//! All times are UTC, since scrobbles do not record the listener's timezone.
+
+use std::collections::BTreeMap;
+
+use chrono::DateTime;
+use chrono::Datelike;
+use chrono::NaiveDate;
+use chrono::Utc;
+use maud::html;
+use maud::Markup;
+use serde_json::json;
+use serde_json::Value;
+use urlencoding::encode;
+
+use crate::get_scrobble_artists;
+use crate::get_scrobble_days;
+use crate::get_scrobble_heatmap;
+use crate::get_yearly_top_artists;
+use crate::html;
+use crate::SqPool;
+
+/// Artists not played in this many days (before the most recent scrobble) are
+/// considered forgotten
+const FORGOTTEN_DAYS: i64 = 180;
+/// Only this many all-time top artists are candidates for forgotten favourites
+const FORGOTTEN_CANDIDATES: usize = 100;
+const FORGOTTEN_SHOWN: usize = 20;
+/// Top artists shown per year
+const YEAR_TOP: usize = 5;
+
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Streaks {
+    /// Length (in days) and first day of the longest run of consecutive days
+    /// with at least one scrobble
+    pub longest: Option<(u64, NaiveDate)>,
+    /// Length of the run ending today (or yesterday, if nothing has been
+    /// scrobbled yet today)
+    pub current: u64,
+}
+
+pub struct Stats {
+    user: String,
+    total: usize,
+
+    /// Plays per hour of day
+    pub clock: [u64; 24],
+    /// Plays per weekday (Monday first) and hour
+    pub heatmap: [[u64; 24]; 7],
+    pub streaks: Streaks,
+    /// Number of artists first scrobbled in each month (`YYYY-MM`), in
+    /// chronological order, starting in January (see `fill_months`)
+    pub discoveries: Vec<(String, u64)>,
+    /// Artists with many plays overall, but none recently, as `(artist,
+    /// plays, last played)`, most played first
+    pub forgotten: Vec<(String, u64, NaiveDate)>,
+    /// Top artists (with plays) of each year, in chronological order
+    pub years: Vec<(i32, Vec<(String, u64)>)>,
+}
+
+fn longest_streak(days: &[NaiveDate]) -> Option<(u64, NaiveDate)> {
+    let mut best: Option<(u64, NaiveDate)> = None;
+    let mut run = (0, *days.first()?);
+    for (i, day) in days.iter().enumerate() {
+        run = match i > 0 && days[i - 1].succ_opt() == Some(*day) {
+            true => (run.0 + 1, run.1),
+            false => (1, *day),
+        };
+        if best.is_none_or(|b| b.0 < run.0) {
+            best = Some(run);
+        }
+    }
+    best
+}
+
+fn current_streak(
+    days: &[NaiveDate],
+    today: NaiveDate,
+) -> u64 {
+    let Some(last) = days.last() else {
+        return 0;
+    };
+    if *last != today && last.succ_opt() != Some(today) {
+        return 0;
+    }
+    days.iter()
+        .rev()
+        .zip(0..)
+        .take_while(|(day, i)| last.checked_sub_days(chrono::Days::new(*i)) == Some(**day))
+        .count() as u64
+}
+
+/// Every month from January of the first year to the last month, including
+/// those without any count, so that every 12th bar is a January
+fn fill_months(counts: &BTreeMap<(i32, u32), u64>) -> Vec<(String, u64)> {
+    let (Some(first), Some(last)) = (counts.keys().next(), counts.keys().next_back()) else {
+        return vec![];
+    };
+    let mut months = vec![];
+    let (mut year, mut month) = (first.0, 1);
+    while (year, month) <= *last {
+        let n = counts.get(&(year, month)).copied().unwrap_or(0);
+        months.push((format!("{year}-{month:02}"), n));
+        (year, month) = match month {
+            12 => (year + 1, 1),
+            _ => (year, month + 1),
+        };
+    }
+    months
+}
+
+impl Stats {
+    /// All aggregates are computed by the db, so that scrobbles are never
+    /// loaded in full. Artists are compared case-insensitively.
+    pub async fn new(
+        user: &str,
+        pool: &SqPool,
+    ) -> anyhow::Result<Self> {
+        Self::at(user, pool, Utc::now().date_naive()).await
+    }
+
+    async fn at(
+        user: &str,
+        pool: &SqPool,
+        today: NaiveDate,
+    ) -> anyhow::Result<Self> {
+        let mut total = 0;
+        let mut clock = [0; 24];
+        let mut heatmap = [[0; 24]; 7];
+        for (weekday, hour, plays) in get_scrobble_heatmap(pool, user).await? {
+            let plays = plays as u64;
+            heatmap[weekday as usize][hour as usize] = plays;
+            clock[hour as usize] += plays;
+            total += plays as usize;
+        }
+
+        let days = get_scrobble_days(pool, user)
+            .await?
+            .iter()
+            .map(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d"))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // most played first
+        let artists = get_scrobble_artists(pool, user).await?;
+        let date = |ts: i64| DateTime::from_timestamp(ts, 0).unwrap_or_default();
+
+        let mut months: BTreeMap<(i32, u32), u64> = BTreeMap::new();
+        for (_, _, first, _) in &artists {
+            let first = date(*first);
+            *months.entry((first.year(), first.month())).or_default() += 1;
+        }
+
+        let forgotten = match artists.iter().map(|a| a.3).max() {
+            None => vec![],
+            Some(latest) => {
+                let cutoff = date(latest) - chrono::Duration::days(FORGOTTEN_DAYS);
+                artists
+                    .iter()
+                    .take(FORGOTTEN_CANDIDATES)
+                    .filter(|a| date(a.3) < cutoff)
+                    .take(FORGOTTEN_SHOWN)
+                    .map(|(a, n, _, last)| (a.clone(), *n as u64, date(*last).date_naive()))
+                    .collect()
+            }
+        };
+
+        let mut years: Vec<(i32, Vec<(String, u64)>)> = vec![];
+        for (year, artist, plays) in get_yearly_top_artists(pool, user, YEAR_TOP as i64).await? {
+            let year = year as i32;
+            match years.last_mut() {
+                Some((y, top)) if *y == year => top.push((artist, plays as u64)),
+                _ => years.push((year, vec![(artist, plays as u64)])),
+            }
+        }
+
+        Ok(Self {
+            user: user.to_string(),
+            total,
+            clock,
+            heatmap,
+            streaks: Streaks {
+                longest: longest_streak(&days),
+                current: current_streak(&days, today),
+            },
+            discoveries: fill_months(&months),
+            forgotten,
+            years,
+        })
+    }
+
+    /// Used by the JSON API. `heatmap` is indexed by weekday (Monday first),
+    /// then hour.
+    pub fn as_json(&self) -> Value {
+        let longest = self
+            .streaks
+            .longest
+            .map(|(days, start)| json!({ "days": days, "start": start.to_string() }));
+        let discoveries: Vec<Value> = self
+            .discoveries
+            .iter()
+            .map(|(month, n)| json!({ "month": month, "artists": n }))
+            .collect();
+        let forgotten: Vec<Value> = self
+            .forgotten
+            .iter()
+            .map(|(artist, plays, last)| {
+                json!({ "artist": artist, "plays": plays, "last_played": last.to_string() })
+            })
+            .collect();
+        let years: Vec<Value> = self
+            .years
+            .iter()
+            .map(|(year, artists)| {
+                let artists: Vec<Value> = artists
+                    .iter()
+                    .map(|(name, plays)| json!({ "name": name, "plays": plays }))
+                    .collect();
+                json!({ "year": year, "artists": artists })
+            })
+            .collect();
+        json!({
+            "user": self.user,
+            "total": self.total,
+            "clock": self.clock,
+            "heatmap": self.heatmap,
+            "streaks": { "longest": longest, "current": self.streaks.current },
+            "discoveries": discoveries,
+            "forgotten": forgotten,
+            "years": years,
+        })
+    }
+
+    /// Vertical bars, with every `label_every`th label shown
+    fn bar_svg(
+        bars: &[(String, u64)],
+        label_every: usize,
+    ) -> Markup {
+        let (bar_width, height, label_height) = (20, 150, 20);
+        let max = bars.iter().map(|b| b.1).max().unwrap_or(1).max(1);
+
+        html! {
+            svg
+                xmlns="http://www.w3.org/2000/svg"
+                width=(bar_width * bars.len())
+                height=(height + label_height)
+                {
+                    @for (i, (label, n)) in bars.iter().enumerate() {
+                        @let h = *n as f64 / max as f64 * height as f64;
+                        @let x = i * bar_width;
+                        rect
+                            x=(x + 2)
+                            y=(format!("{:.1}", height as f64 - h))
+                            width=(bar_width - 4)
+                            height=(format!("{:.1}", h))
+                            fill="steelblue"
+                            { title { (label) ": " (n) } }
+                        @if i % label_every.max(1) == 0 {
+                            text
+                                x=(x + bar_width / 2)
+                                y=(height + 15)
+                                text-anchor="middle"
+                                font-size="10"
+                                { (label) }
+                        }
+                    }
+                }
+        }
+    }
+
+    fn heatmap_svg(&self) -> Markup {
+        let (cell, label_width) = (20, 40);
+        let max = self.heatmap.iter().flatten().max().copied().unwrap_or(1).max(1);
+
+        html! {
+            svg
+                xmlns="http://www.w3.org/2000/svg"
+                width=(label_width + cell * 24)
+                height=(cell * 8)
+                {
+                    @for (d, hours) in self.heatmap.iter().enumerate() {
+                        text x="0" y=(d * cell + 15) font-size="12" { (WEEKDAYS[d]) }
+                        @for (h, n) in hours.iter().enumerate() {
+                            rect
+                                x=(label_width + h * cell)
+                                y=(d * cell)
+                                width=(cell - 2)
+                                height=(cell - 2)
+                                fill="steelblue"
+                                fill-opacity=(format!("{:.2}", 0.05 + 0.95 * *n as f64 / max as f64))
+                                { title { (WEEKDAYS[d]) " " (h) ":00: " (n) } }
+                        }
+                    }
+                    @for h in (0..24).step_by(3) {
+                        text
+                            x=(label_width + h * cell + cell / 2)
+                            y=(cell * 7 + 15)
+                            text-anchor="middle"
+                            font-size="10"
+                            { (h) }
+                    }
+                }
+        }
+    }
+
+    pub fn as_html(&self) -> Markup {
+        let user = &self.user;
+        let artist_link = |a: &str| html::link(&format!("/artists/{}", encode(a)), a);
+
+        let clock: Vec<(String, u64)> = self
+            .clock
+            .iter()
+            .enumerate()
+            .map(|(h, n)| (h.to_string(), *n))
+            .collect();
+
+        html! {
+            (html::header(&format!("Stats for {user}")))
+            p { (html::link(&format!("/charts/{}/local", encode(user)), "Local chart")) }
+
+            @if self.total == 0 {
+                p { "No imported scrobbles for " (user) " (see `lasttree import`)." }
+            } @else {
+                p { (self.total) " scrobbles (times are UTC)" }
+
+                h3 { "Streaks" }
+                p {
+                    @if let Some((days, start)) = self.streaks.longest {
+                        "Longest: " (days) " days, from " (start) br;
+                    }
+                    "Current: " (self.streaks.current) " days"
+                }
+
+                details open {
+                    summary { "Listening clock" }
+                    (Self::bar_svg(&clock, 3))
+                }
+
+                details open {
+                    summary { "Day of week" }
+                    (self.heatmap_svg())
+                }
+
+                details open {
+                    summary { "New artists per month" }
+                    (Self::bar_svg(&self.discoveries, 12))
+                }
+
+                h3 { "Forgotten favourites" }
+                @if self.forgotten.is_empty() {
+                    p { "None." }
+                }
+                ul {
+                    @for (artist, plays, last) in &self.forgotten {
+                        li { (artist_link(artist)) " (" (plays) " plays, last " (last) ")" }
+                    }
+                }
+
+                h3 { "Top artists per year" }
+                @for (year, artists) in self.years.iter().rev() {
+                    p {
+                        b { (year) } ": "
+                        @for (i, (artist, plays)) in artists.iter().enumerate() {
+                            @if i > 0 { ", " }
+                            (artist_link(artist)) " (" (plays) ")"
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::Stats;
+    use crate::scrobbles::Scrobble;
+    use crate::store_scrobbles;
+    use crate::tests::TestPool;
+
+    #[tokio::test]
+    async fn aggregates() {
+        let pool = &TestPool::new(None).await.pool;
+        let ts = |d: &str| {
+            chrono::NaiveDateTime::parse_from_str(d, "%Y-%m-%d %H:%M")
+                .unwrap()
+                .and_utc()
+                .timestamp()
+        };
+        let date = |d: &str| NaiveDate::parse_from_str(d, "%Y-%m-%d").unwrap();
+        let scrobbles: Vec<Scrobble> = [
+            ("Metallica", "2022-01-01 10:00"),
+            ("Metallica", "2022-01-02 10:30"),
+            ("metallica", "2022-01-03 22:00"),
+            ("Loona", "2024-03-01 10:00"),
+            ("Loona", "2024-03-02 10:00"),
+        ]
+        .iter()
+        .map(|(a, d)| Scrobble {
+            artist: a.to_string(),
+            track: "x".to_string(),
+            album: None,
+            timestamp: ts(d),
+        })
+        .collect();
+        store_scrobbles(pool, "foo", &scrobbles).await.unwrap();
+
+        let stats = Stats::at("foo", pool, date("2024-03-03")).await.unwrap();
+
+        assert_eq!(stats.total, 5);
+        assert_eq!(stats.clock[10], 4);
+        assert_eq!(stats.clock[22], 1);
+        // 2022-01-01 and 2024-03-02 were Saturdays
+        assert_eq!(stats.heatmap[5][10], 2);
+
+        assert_eq!(stats.streaks.longest, Some((3, date("2022-01-01"))));
+        assert_eq!(stats.streaks.current, 2);
+
+        // 2022-01 to 2024-03, including empty months
+        assert_eq!(stats.discoveries.len(), 27);
+        assert_eq!(stats.discoveries[0], ("2022-01".to_string(), 1));
+        assert_eq!(stats.discoveries[1], ("2022-02".to_string(), 0));
+        assert_eq!(stats.discoveries[24], ("2024-01".to_string(), 0));
+        assert_eq!(stats.discoveries[26], ("2024-03".to_string(), 1));
+
+        assert_eq!(
+            stats.forgotten,
+            [("Metallica".to_string(), 3, date("2022-01-03"))]
+        );
+        assert_eq!(stats.years[0], (2022, vec![("Metallica".to_string(), 3)]));
+
+        let stats = Stats::at("foo", pool, date("2024-03-05")).await.unwrap();
+        assert_eq!(stats.streaks.current, 0);
+    }
+}
@@ -0,0 +1,267 @@
+//! Graphs of related tags, analogous to `ArtistTree`. Starting at a tag,
+//! related tags are found either via `tag.getSimilar`, or by co-occurrence
+//! among the tags of cached artists. The latter requires no network access,
+//! but is only as good as the local db.
+
+use std::collections::HashMap;
+use std::collections::VecDeque;
+
+use maud::html;
+use maud::Markup;
+use maud::PreEscaped;
+use petgraph::graph::NodeIndex;
+use petgraph::Graph;
+use serde::Deserialize;
+use strum::IntoEnumIterator;
+use urlencoding::encode;
+
+use crate::genre_link;
+use crate::get_similar_genres;
+use crate::get_tag_cooccurrence;
+use crate::html;
+use crate::lastfm_key;
+use crate::SqPool;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, strum_macros::EnumIter)]
+#[serde(rename_all = "lowercase")]
+pub enum TagSource {
+    /// Co-occurrence among cached artists
+    #[default]
+    Local,
+    /// `tag.getSimilar`
+    Lastfm,
+}
+
+impl std::fmt::Display for TagSource {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter,
+    ) -> std::fmt::Result {
+        let s = match self {
+            TagSource::Local => "local",
+            TagSource::Lastfm => "lastfm",
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// Max expansion depth; with the default `limit`, at most 1 + 5 + 25 tags are
+/// expanded
+const MAX_DEPTH: u32 = 3;
+
+pub struct TagGraph {
+    /// Lowercase
+    pub root: String,
+    source: TagSource,
+    depth: u32,
+    /// Max number of related tags fetched per tag
+    limit: u32,
+    /// Default: the shared key. See `lastfm_key`
+    key: Option<String>,
+
+    nodes: HashMap<String, NodeIndex>,
+    /// Edge weights range from 0 to 100, like artist similarity
+    pub graph: Graph<String, i64>,
+}
+
+impl TagGraph {
+    pub fn new(root: &str) -> Self {
+        let root = root.to_lowercase();
+        let mut graph = Graph::new();
+        let node = graph.add_node(root.clone());
+        Self {
+            nodes: HashMap::from([(root.clone(), node)]),
+            root,
+            source: TagSource::default(),
+            depth: 2,
+            limit: 5,
+            key: None,
+            graph,
+        }
+    }
+
+    pub fn with_source(
+        mut self,
+        source: TagSource,
+    ) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Use an account's own API key (if any) instead of the shared one
+    pub fn with_key(
+        mut self,
+        key: Option<String>,
+    ) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// Clamped to `MAX_DEPTH`, since the number of requests grows
+    /// exponentially with depth (`limit` per tag)
+    pub fn with_depth(
+        mut self,
+        depth: u32,
+    ) -> Self {
+        self.depth = depth.min(MAX_DEPTH);
+        self
+    }
+
+    /// For `TagSource::Local`, the weight of each related tag is the percentage
+    /// of artists tagged with `tag` that also carry it. `tag.getSimilar`
+    /// returns no scores, so weights are derived from rank instead.
+    async fn get_related(
+        &self,
+        pool: &SqPool,
+        tag: &str,
+    ) -> anyhow::Result<Vec<(String, i64)>> {
+        let related = match self.source {
+            TagSource::Local => {
+                let (total, rows) = get_tag_cooccurrence(pool, tag, self.limit).await?;
+                rows.into_iter()
+                    .map(|(t, count)| (t, count * 100 / total.max(1)))
+                    .collect()
+            }
+            TagSource::Lastfm => {
+                let key = lastfm_key(pool, self.key.as_deref()).await?;
+                let similar = get_similar_genres(tag, &key).await?;
+                let n = similar.len().min(self.limit as usize) as i64;
+                similar
+                    .into_iter()
+                    .take(n as usize)
+                    .enumerate()
+                    .map(|(i, t)| (t.to_lowercase(), 100 * (n - i as i64) / n))
+                    .collect()
+            }
+        };
+        Ok(related)
+    }
+
+    fn get_or_add_node(
+        &mut self,
+        tag: &str,
+    ) -> (NodeIndex, bool) {
+        match self.nodes.get(tag) {
+            Some(node) => (*node, false),
+            None => {
+                let node = self.graph.add_node(tag.to_string());
+                self.nodes.insert(tag.to_string(), node);
+                (node, true)
+            }
+        }
+    }
+
+    /// Breadth-first expansion from the root. Tags already in the graph are
+    /// linked rather than expanded again.
+    pub async fn build(
+        mut self,
+        pool: &SqPool,
+    ) -> anyhow::Result<Self> {
+        let mut queue = VecDeque::from([(self.root.clone(), 0)]);
+
+        while let Some((tag, depth)) = queue.pop_front() {
+            if depth >= self.depth {
+                continue;
+            }
+
+            let parent = self.nodes[&tag];
+            for (child, weight) in self.get_related(pool, &tag).await? {
+                let (node, is_new) = self.get_or_add_node(&child);
+                if node == parent
+                    || self.graph.contains_edge(parent, node)
+                    || self.graph.contains_edge(node, parent)
+                {
+                    continue;
+                }
+                self.graph.add_edge(parent, node, weight);
+                if is_new {
+                    queue.push_back((child, depth + 1));
+                }
+            }
+        }
+
+        Ok(self)
+    }
+
+    pub fn as_html(&self) -> Markup {
+        let root = &self.root;
+
+        let sources = html! {
+            @for s in TagSource::iter() {
+                @if s == self.source {
+                    b { (s) }
+                } @else {
+                    (html::link(&format!("/genres/{}/graph?source={s}", encode(root)), &s.to_string()))
+                }
+                " "
+            }
+        };
+
+        html! {
+            (html::header(&format!("Tag graph: {root}")))
+            p { (genre_link(root)) }
+            p { "Source: " (sources) }
+
+            @if self.graph.node_count() == 1 {
+                p {
+                    "No related tags found."
+                    @if self.source == TagSource::Local {
+                        " Tags are only known for cached artists; try "
+                        (html::link("/admin/seed", "seeding"))
+                        " the db."
+                    }
+                }
+            } @else {
+                (PreEscaped(&self.as_svg()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::artists::Artist;
+    use crate::tag_graph::TagGraph;
+    use crate::tests::TestPool;
+
+    #[tokio::test]
+    async fn cooccurrence() {
+        let pool = &TestPool::new(None).await.pool;
+
+        for (name, tags) in [
+            ("A", vec!["Black Metal", "Death Metal"]),
+            ("B", vec!["black metal", "Ambient"]),
+            ("C", vec!["black metal", "death metal"]),
+            ("D", vec!["ambient", "drone"]),
+        ] {
+            let a = Artist::new(name);
+            a.store(pool, name).await.unwrap();
+            let tags = tags.into_iter().map(|t| t.to_owned()).collect();
+            a.store_tags(pool, &tags).await.unwrap();
+        }
+
+        let graph = TagGraph::new("Black Metal")
+            .with_depth(1)
+            .build(pool)
+            .await
+            .unwrap();
+
+        let mut edges: Vec<(&str, i64)> = graph
+            .graph
+            .edge_indices()
+            .map(|e| {
+                let (_, trg) = graph.graph.edge_endpoints(e).unwrap();
+                (graph.graph[trg].as_str(), graph.graph[e])
+            })
+            .collect();
+        edges.sort();
+
+        // drone only co-occurs with ambient, which is beyond depth 1
+        assert_eq!(edges, vec![("ambient", 33), ("death metal", 66)]);
+    }
+
+    #[test]
+    fn max_depth() {
+        assert_eq!(TagGraph::new("rock").with_depth(100).depth, super::MAX_DEPTH);
+    }
+}
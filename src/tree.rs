@@ -4,6 +4,8 @@
 // "edge-only" Vec<Edge>. then i also found -that- ugly, and switched to a
 // HashMap (and later IndexMap).
 
+use std::collections::HashMap;
+use std::collections::HashSet;
 use std::fmt::Debug;
 use std::fmt::Display;
 
@@ -13,10 +15,14 @@ use petgraph::algo::astar;
 use petgraph::graph::Graph;
 use petgraph::graph::NodeIndex;
 use petgraph::visit::NodeIndexable;
+use petgraph::Direction;
+use serde::Deserialize;
 
 use crate::artists::Artist;
-// use crate::artists::LastfmError;
+use crate::artists::LastfmError;
+use crate::config::config;
 use crate::SqPool;
+use crate::OFFLINE;
 
 /// Convert arbitrary error types to `actix_web::Error` with HTTP 500. Note
 /// that, as the type signature suggests, `T` must implement `Display`.
@@ -27,6 +33,15 @@ where
     actix_web::error::ErrorInternalServerError(e)
 }
 
+/// Like `error_500`, but errors that wrap a `LastfmError` (e.g. `UserNotFound`)
+/// are rendered by its `ResponseError` impl
+pub fn error_lastfm(e: anyhow::Error) -> actix_web::Error {
+    match e.downcast::<LastfmError>() {
+        Ok(e) => e.into(),
+        Err(e) => error_500(e),
+    }
+}
+
 impl Artist {
     pub async fn get_cached_similar_artists(
         &self,
@@ -49,6 +64,110 @@ impl Artist {
         }
     }
 }
+/// Restricts tree expansion to less popular artists, which makes trees drift
+/// towards more obscure artists. If both conditions are set, both must hold
+/// for a child to be added.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct ListenerFilter {
+    /// Only add children with fewer listeners than their parent
+    #[serde(default)]
+    pub fewer_than_parent: bool,
+
+    /// Only add children with fewer listeners than this
+    pub max_listeners: Option<u32>,
+}
+
+impl ListenerFilter {
+    pub fn is_active(&self) -> bool { self.fewer_than_parent || self.max_listeners.is_some() }
+
+    /// Listener counts are fetched (and cached) via `Artist::get_listeners`. A
+    /// child whose listener count cannot be determined is rejected.
+    async fn accepts(
+        &self,
+        pool: &SqPool,
+        parent: &str,
+        child: &str,
+        offline: bool,
+        key: Option<&str>,
+    ) -> bool {
+        if !self.is_active() {
+            return true;
+        }
+
+        let Ok(listeners) = Artist::new(child)
+            .with_offline(offline)
+            .with_key(key.map(String::from))
+            .get_listeners(pool)
+            .await
+        else {
+            return false;
+        };
+
+        if let Some(max) = self.max_listeners {
+            if listeners >= max {
+                return false;
+            }
+        }
+
+        if self.fewer_than_parent {
+            match Artist::new(parent)
+                .with_offline(offline)
+                .with_key(key.map(String::from))
+                .get_listeners(pool)
+                .await
+            {
+                Ok(parent_listeners) if listeners < parent_listeners => (),
+                _ => return false,
+            }
+        }
+
+        true
+    }
+}
+
+/// Keeps tree expansion within a genre, e.g. only through "death metal" or
+/// "technical death metal". Tags are compared case-insensitively.
+#[derive(Debug, Default, Clone)]
+pub struct TagFilter {
+    /// If non-empty, children must have at least one of these tags
+    pub include: Vec<String>,
+
+    /// Children must have none of these tags
+    pub exclude: Vec<String>,
+}
+
+impl TagFilter {
+    pub fn is_active(&self) -> bool { !self.include.is_empty() || !self.exclude.is_empty() }
+
+    /// Tags are taken from the `artists.tags` column, and fetched via
+    /// `Artist::get_tags` if missing. A child whose tags cannot be determined is
+    /// rejected.
+    async fn accepts(
+        &self,
+        pool: &SqPool,
+        child: &str,
+        offline: bool,
+        key: Option<&str>,
+    ) -> bool {
+        if !self.is_active() {
+            return true;
+        }
+
+        let Ok(tags) = Artist::new(child)
+            .with_offline(offline)
+            .with_key(key.map(String::from))
+            .get_tags(pool)
+            .await
+        else {
+            return false;
+        };
+        let tags: HashSet<String> = tags.iter().map(|t| t.to_lowercase()).collect();
+        let has_any = |wanted: &[String]| wanted.iter().any(|t| tags.contains(&t.to_lowercase()));
+
+        (self.include.is_empty() || has_any(&self.include)) && !has_any(&self.exclude)
+    }
+}
+
 // #[derive(Debug)]
 // raw json -> IndexMap (+ db rows) -> Graph -> Dot -> html
 // TODO: at some point, this should be made a (public) field of Artist
@@ -56,10 +175,16 @@ impl Artist {
 /// for retrieving similar `Artist`s (i.e. edges).
 ///
 /// This is implemented as a tree (specifically, an adjacency list); graphs will
-/// usually produce many uninteresting cycles.
+/// usually produce many uninteresting cycles. The only exception is a tree with
+/// multiple seeds, where descendants shared by several parents are merged.
 pub struct ArtistTree {
+    /// The first seed
     pub root: String,
 
+    /// Usually just `root`. Use `from_seeds` to expand from several roots at
+    /// once.
+    seeds: Vec<String>,
+
     // TODO:
     // pub nodes: IndexMap<Artist, NodeIndex>,
     /// `IndexMap` is used to preserve insertion order of nodes (`HashMap`s are
@@ -67,33 +192,201 @@ pub struct ArtistTree {
     nodes: IndexMap<String, NodeIndex>,
 
     #[allow(dead_code)]
-    /// Default: 0.7
+    /// Default: `tree.threshold` (0.7)
     threshold: f64, // TODO: use int
 
     #[allow(dead_code)]
-    /// Default: 2
+    /// Default: `tree.depth` (2)
     depth: u8,
 
+    /// Default: inactive
+    listener_filter: ListenerFilter,
+
+    /// Default: inactive
+    tag_filter: TagFilter,
+
+    /// Candidates rejected by a filter that did not make it into the tree via
+    /// another parent
+    pruned: HashSet<String>,
+
+    /// Default: false. See `Artist::with_offline`
+    offline: bool,
+
+    /// In offline mode, nodes that could not be expanded because their similar
+    /// artists are not cached
+    uncached: HashSet<String>,
+
+    /// Default: the key in the `api_key` table. See `Artist::with_key`
+    key: Option<String>,
+
+    /// Lowercase. Never added to the tree (but seeds are always kept)
+    ignored: HashSet<String>,
+
+    /// Show buttons to add nodes to the logged-in account's lists
+    account_actions: bool,
+
+    /// Artists in the user's library (lowercase), with their playcounts. See
+    /// `User::get_library`
+    known: HashMap<String, u64>,
+
+    /// Hide known artists from the table (they are kept in the tree)
+    hide_known: bool,
+
     pub graph: Graph<String, i64>,
 }
 
 impl ArtistTree {
-    /// Defaults to `threshold` 0.7, `depth` 2
+    /// Defaults to `threshold` and `depth` from the `tree` config section
     pub fn new(root: &str) -> Self {
         let root = root.to_string();
         let nodes = IndexMap::new();
-        let threshold = 0.7;
-        let depth = 2;
+        let threshold = config().tree.threshold;
+        let depth = config().tree.depth;
 
         Self {
+            seeds: vec![root.clone()],
             root,
             nodes,
             threshold,
             depth,
+            listener_filter: ListenerFilter::default(),
+            tag_filter: TagFilter::default(),
+            pruned: HashSet::new(),
+            offline: false,
+            uncached: HashSet::new(),
+            key: None,
+            ignored: HashSet::new(),
+            account_actions: false,
+            known: HashMap::new(),
+            hide_known: false,
             graph: Graph::new(),
         }
     }
 
+    /// Like `new`, but expands from all `seeds` at once, in order to find artists
+    /// similar to all of them.
+    ///
+    /// Panics if `seeds` is empty.
+    pub fn from_seeds(seeds: &[String]) -> Self {
+        let mut tree = Self::new(&seeds[0]);
+        tree.seeds = seeds.to_vec();
+        tree
+    }
+
+    /// Canonical names, once the tree has been built
+    pub fn seeds(&self) -> &[String] { &self.seeds }
+
+    pub fn is_seed(
+        &self,
+        artist: &str,
+    ) -> bool {
+        self.seeds.iter().any(|s| s == artist)
+    }
+
+    pub fn with_listener_filter(
+        mut self,
+        filter: ListenerFilter,
+    ) -> Self {
+        self.listener_filter = filter;
+        self
+    }
+
+    pub fn with_tag_filter(
+        mut self,
+        filter: TagFilter,
+    ) -> Self {
+        self.tag_filter = filter;
+        self
+    }
+
+    /// Build the tree only from cached data. Seeds must be cached; other nodes
+    /// that are not are left unexpanded (see `is_uncached`).
+    pub fn with_offline(
+        mut self,
+        offline: bool,
+    ) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    pub fn with_key(
+        mut self,
+        key: Option<String>,
+    ) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// `ignored` must be lowercase
+    pub fn with_ignored(
+        mut self,
+        ignored: HashSet<String>,
+    ) -> Self {
+        self.ignored = ignored;
+        self
+    }
+
+    pub fn with_account_actions(
+        mut self,
+        enabled: bool,
+    ) -> Self {
+        self.account_actions = enabled;
+        self
+    }
+
+    pub fn has_account_actions(&self) -> bool { self.account_actions }
+
+    /// `known` must be keyed by lowercase name
+    pub fn with_known(
+        mut self,
+        known: HashMap<String, u64>,
+    ) -> Self {
+        self.known = known;
+        self
+    }
+
+    pub fn with_hide_known(
+        mut self,
+        hide: bool,
+    ) -> Self {
+        self.hide_known = hide;
+        self
+    }
+
+    /// The user's playcount of `artist`, if it is in their library
+    pub fn known_plays(
+        &self,
+        artist: &str,
+    ) -> Option<u64> {
+        self.known.get(&artist.to_lowercase()).copied()
+    }
+
+    pub fn has_known(&self) -> bool { !self.known.is_empty() }
+
+    pub fn hides_known(&self) -> bool { self.hide_known }
+
+    pub fn is_offline(&self) -> bool { self.offline || *OFFLINE }
+
+    /// Whether `artist` was left unexpanded because its similar artists are not
+    /// cached (offline mode only)
+    pub fn is_uncached(
+        &self,
+        artist: &str,
+    ) -> bool {
+        self.uncached.contains(artist)
+    }
+
+    /// Number of nodes left unexpanded in offline mode
+    pub fn uncached(&self) -> usize { self.uncached.len() }
+
+    /// Whether any filter will be applied when building the tree
+    pub fn is_filtered(&self) -> bool {
+        self.listener_filter.is_active() || self.tag_filter.is_active()
+    }
+
+    /// Number of candidates rejected by filters
+    pub fn pruned(&self) -> usize { self.pruned.len() }
+
     /// Wrapper for `IndexMap.keys()` (`self.nodes` is kept private)
     pub fn nodes(&self) -> impl Iterator<Item = &String> { self.nodes.keys() }
 
@@ -117,53 +410,93 @@ impl ArtistTree {
     /// track of what has been added to the `Graph`. It is not otherwise
     /// used.
     ///
-    /// Note: `self.root` will be replaced with the canonical name.
+    /// Note: `self.root` (and `self.seeds`) will be replaced with the canonical
+    /// name(s).
     pub async fn build_tree(
         mut self,
         pool: &SqPool,
     ) -> anyhow::Result<Self> {
+        let threshold: i64 = ((self.threshold * 100.0) as u32).into();
         for i in 0..=self.depth {
             let parents = match i {
                 0 => {
                     // we literally only do this in order to store the canonical name in the db and
                     // get it back; the map returned by the function doesn't actually contain it!
 
-                    let root = Artist::new(&self.root);
-                    root.get_similar_artists(pool).await?;
-                    let canon = root.canonical_name(pool).await?.context("fjdaks")?;
+                    let mut canons = vec![];
+                    for seed in self.seeds.iter() {
+                        let artist = Artist::new(seed)
+                            .with_offline(self.offline)
+                            .with_key(self.key.clone());
+                        artist.get_similar_artists(pool).await?;
+                        let canon = artist
+                            .canonical_name(pool)
+                            .await?
+                            .context(format!("Artist not found: {seed}"))?;
+                        if !canons.contains(&canon) {
+                            canons.push(canon);
+                        }
+                    }
                     // println!("{:?}", canon);
-                    self.root = canon.clone(); // override with the canonical
+                    self.root = canons[0].clone(); // override with the canonical
+                    self.seeds = canons.clone();
 
                     // println!("{:#?}", self);
-                    [canon].to_vec()
+                    canons
                 }
                 _ => self.nodes.clone().into_keys().collect(),
             };
 
             for parent in parents {
+                if self.uncached.contains(&parent) {
+                    continue;
+                }
+
                 // deal with LastmError variants here (instead of ?)
-                let artist = Artist::new(&parent);
+                let artist = Artist::new(&parent)
+                    .with_offline(self.offline)
+                    .with_key(self.key.clone());
                 let map = match artist.get_similar_artists(pool).await {
                     Ok(m) => m,
+                    Err(LastfmError::NotCached(_)) => {
+                        self.uncached.insert(parent);
+                        continue;
+                    }
                     Err(e) => return Err(anyhow::anyhow!(e)),
                 };
 
                 for (c, sim) in map
                     .iter()
-                    .filter(|x| *x.1 >= ((self.threshold * 100.0) as u32).into())
+                    .filter(|x| *x.1 >= threshold)
                 {
-                    let n1 = match self.nodes.get(&parent) {
-                        Some(node) => *node,
-                        None => self.graph.add_node(parent.clone()),
-                    };
-                    let n2 = match self.nodes.get(c) {
-                        Some(_) => continue,
-                        None => self.graph.add_node(c.to_string()),
-                    };
+                    if let Some(&n2) = self.nodes.get(c) {
+                        if self.seeds.len() > 1 {
+                            let n1 = self.get_or_add_node(&parent);
+                            if n1 != n2 && self.graph.find_edge(n1, n2).is_none() {
+                                self.graph.add_edge(n1, n2, *sim);
+                            }
+                        }
+                        continue;
+                    }
+
+                    if self.ignored.contains(&c.to_lowercase()) {
+                        continue;
+                    }
+
+                    let key = self.key.as_deref();
+                    if !self.tag_filter.accepts(pool, c, self.offline, key).await
+                        || !self
+                            .listener_filter
+                            .accepts(pool, &parent, c, self.offline, key)
+                            .await
+                    {
+                        self.pruned.insert(c.to_string());
+                        continue;
+                    }
+
+                    let n1 = self.get_or_add_node(&parent);
+                    let n2 = self.get_or_add_node(c);
                     self.graph.add_edge(n1, n2, *sim);
-
-                    self.nodes.insert(parent.clone(), n1);
-                    self.nodes.insert(c.to_string(), n2);
                     // println!("new node: {c} {n2:?}");
                 }
                 // println!("{:?}", self.nodes);
@@ -174,10 +507,40 @@ impl ArtistTree {
             // panic!();
         }
 
+        let nodes = &self.nodes;
+        self.pruned.retain(|c| !nodes.contains_key(c));
+
         // graph
         Ok(self)
     }
 
+    /// Add an edge from `parent` to `child` directly, without any requests.
+    /// Used to construct graphs that are not built by traversal.
+    pub(crate) fn add_pair(
+        &mut self,
+        parent: &str,
+        child: &str,
+        similarity: i64,
+    ) {
+        let n1 = self.get_or_add_node(parent);
+        let n2 = self.get_or_add_node(child);
+        self.graph.add_edge(n1, n2, similarity);
+    }
+
+    fn get_or_add_node(
+        &mut self,
+        node_label: &str,
+    ) -> NodeIndex {
+        match self.nodes.get(node_label) {
+            Some(node) => *node,
+            None => {
+                let node = self.graph.add_node(node_label.to_string());
+                self.nodes.insert(node_label.to_string(), node);
+                node
+            }
+        }
+    }
+
     fn get_node_index(
         &self,
         node_label: &str,
@@ -199,6 +562,98 @@ impl ArtistTree {
 
         // println!("0 {:#?}", self.graph.node_weight(root)); // n_idx -> Option<str>
 
+        self.get_path_similarity(root, target).unwrap()
+    }
+
+    /// Aggregate similarity of `child` to all seeds, i.e. the mean of its
+    /// similarity to each seed; seeds that cannot reach `child` contribute 0.
+    /// For a tree with a single seed, this is equivalent to
+    /// `get_child_similarity`.
+    pub fn get_seed_similarity(
+        &self,
+        child: &str,
+    ) -> i64 {
+        let Some(target) = self.get_node_index(child) else {
+            return 0;
+        };
+        let total: i64 = self
+            .seeds
+            .iter()
+            .filter_map(|s| self.get_node_index(s))
+            .filter_map(|seed| self.get_path_similarity(seed, target))
+            .sum();
+        total / self.seeds.len() as i64
+    }
+
+    /// Plain text representation for the terminal: one node per line, indented
+    /// by depth, followed by its similarity to its parent. Nodes reachable from
+    /// several parents (multi-seed trees only) are only listed once.
+    pub fn as_outline(&self) -> String {
+        fn visit(
+            tree: &ArtistTree,
+            node: NodeIndex,
+            level: usize,
+            seen: &mut HashSet<NodeIndex>,
+            lines: &mut Vec<String>,
+        ) {
+            // petgraph iterates neighbours in reverse order of insertion
+            let mut children: Vec<(NodeIndex, i64)> = tree
+                .graph
+                .neighbors_directed(node, Direction::Outgoing)
+                .map(|c| (c, tree.graph[tree.graph.find_edge(node, c).unwrap()]))
+                .collect();
+            children.sort_by_key(|c| -c.1);
+
+            for (child, sim) in children {
+                if !seen.insert(child) {
+                    continue;
+                }
+                lines.push(format!("{}{} ({sim})", "  ".repeat(level), tree.graph[child]));
+                visit(tree, child, level + 1, seen, lines);
+            }
+        }
+
+        let mut lines = vec![];
+        let mut seen = HashSet::new();
+        for seed in self.seeds.iter().filter_map(|s| self.get_node_index(s)) {
+            seen.insert(seed);
+            lines.push(self.graph[seed].clone());
+            visit(self, seed, 1, &mut seen, &mut lines);
+        }
+        lines.join("\n")
+    }
+
+    /// The underlying `Graph`, serialised via petgraph's `serde-1` feature
+    pub fn as_json(&self) -> serde_json::Result<String> { serde_json::to_string_pretty(&self.graph) }
+
+    /// Shortest path from the first seed that can reach `artist`, as a list of
+    /// `(node, similarity to previous node)`. The seed itself comes first, with
+    /// a similarity of 100. Returns `None` if `artist` is not in the tree.
+    pub fn get_path(
+        &self,
+        artist: &str,
+    ) -> Option<Vec<(String, i64)>> {
+        let target = self.get_node_index(artist)?;
+        let path = self
+            .seeds
+            .iter()
+            .filter_map(|s| self.get_node_index(s))
+            .find_map(|seed| self.find_path(seed, target))?;
+
+        let mut nodes = vec![(self.graph[path[0]].clone(), 100)];
+        nodes.extend(path.windows(2).map(|pair| {
+            let e = self.graph.find_edge(pair[0], pair[1]).unwrap();
+            (self.graph[pair[1]].clone(), self.graph[e])
+        }));
+        Some(nodes)
+    }
+
+    /// Returns `None` if there is no path from `source` to `target`
+    fn find_path(
+        &self,
+        source: NodeIndex,
+        target: NodeIndex,
+    ) -> Option<Vec<NodeIndex>> {
         // generally, Graph methods only operate on single edges. to get a path between
         // 2 arbitrary edges, an `algorithm` is required
 
@@ -206,22 +661,33 @@ impl ArtistTree {
         let path = astar(
             //
             &self.graph,
-            root,
+            source,
             |n| n == target,
             |_| 1,
             |_| 1,
-        )
-        .unwrap()
+        )?
         .1;
+        Some(path)
+    }
+
+    /// Returns `None` if there is no path from `source` to `target`
+    fn get_path_similarity(
+        &self,
+        source: NodeIndex,
+        target: NodeIndex,
+    ) -> Option<i64> {
+        let path = self.find_path(source, target)?;
 
         // let x = self.graph.index_twice_mut(path[0], path[3]);
         // println!("{:?}", x);
 
         // https://github.com/a-b-street/abstreet/blob/35d669cf7aa9b6d24cd0cfe423f0dfc4037b4357/map_model/src/map.rs#L880
-        path.windows(2)
+        let sim = path
+            .windows(2)
             .map(|pair| self.graph.find_edge(pair[0], pair[1]).unwrap())
             .map(|e| self.graph.edge_weight(e).unwrap())
-            .fold(100, |acc, x| (acc * x) / 100)
+            .fold(100, |acc, x| (acc * x) / 100);
+        Some(sim)
     }
 }
 
@@ -279,6 +745,126 @@ mod tests {
         .await;
     }
 
+    #[tokio::test]
+    async fn fewer_listeners() {
+        use petgraph::visit::EdgeRef;
+
+        use super::ListenerFilter;
+        use crate::artists::Artist;
+
+        let pool = &TestPool::new(Some(&LASTFM_KEY)).await.pool;
+        let filter = ListenerFilter {
+            fewer_than_parent: true,
+            max_listeners: None,
+        };
+        let tree = ArtistTree::new("metallica")
+            .with_listener_filter(filter)
+            .build_tree(pool)
+            .await
+            .unwrap();
+
+        for e in tree.graph.edge_references() {
+            let parent = Artist::new(&tree.graph[e.source()]);
+            let child = Artist::new(&tree.graph[e.target()]);
+            assert!(
+                child.get_listeners(pool).await.unwrap() < parent.get_listeners(pool).await.unwrap()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn tag_filter() {
+        use super::TagFilter;
+        use crate::artists::Artist;
+
+        let pool = &TestPool::new(Some(&LASTFM_KEY)).await.pool;
+        let include = vec!["death metal".to_string(), "technical death metal".to_string()];
+        let filter = TagFilter {
+            include: include.clone(),
+            exclude: vec![],
+        };
+        let tree = ArtistTree::new("pestilence")
+            .with_tag_filter(filter)
+            .build_tree(pool)
+            .await
+            .unwrap();
+
+        for node in tree.nodes().filter(|n| **n != tree.root) {
+            let tags = Artist::new(node).get_tags(pool).await.unwrap();
+            assert!(
+                tags.iter().any(|t| include.contains(&t.to_lowercase())),
+                "{node}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn multiple_seeds() {
+        let pool = &TestPool::new(Some(&LASTFM_KEY)).await.pool;
+        let seeds = ["metallica", "megadeth", "slayer"].map(|s| s.to_string());
+        let tree = ArtistTree::from_seeds(&seeds)
+            .build_tree(pool)
+            .await
+            .unwrap();
+
+        assert_eq!(tree.seeds(), ["Metallica", "Megadeth", "Slayer"]);
+        assert_eq!(tree.root, "Metallica");
+
+        // at least one descendant should be shared by more than one parent
+        assert!(tree.graph.node_indices().any(|n| tree
+            .graph
+            .neighbors_directed(n, petgraph::Direction::Incoming)
+            .count()
+            > 1));
+    }
+
+    #[tokio::test]
+    async fn offline() {
+        let pool = &TestPool::new(Some(&LASTFM_KEY)).await.pool;
+
+        assert!(ArtistTree::new("metallica")
+            .with_offline(true)
+            .build_tree(pool)
+            .await
+            .is_err());
+
+        let online = ArtistTree::new("metallica").build_tree(pool).await.unwrap();
+        let offline = ArtistTree::new("metallica")
+            .with_offline(true)
+            .build_tree(pool)
+            .await
+            .unwrap();
+
+        // children at the last level were never expanded, so everything
+        // required is cached
+        assert_eq!(
+            online.nodes().collect::<Vec<_>>(),
+            offline.nodes().collect::<Vec<_>>()
+        );
+        assert_eq!(offline.uncached(), 0);
+    }
+
+    #[test]
+    fn outline() {
+        let graph = serde_json::json!({
+            "edge_property":"directed",
+            "edges":[[0,1,100],[1,2,95],[0,3,86]],
+            "nodes":["Loona","LOOΠΔ 1/3","LOONA/yyxy","LOOΠΔ / ODD EYE CIRCLE"]
+        });
+
+        let mut tree = ArtistTree::new("Loona");
+        tree.graph = serde_json::from_value(graph).unwrap();
+
+        assert_eq!(
+            tree.as_outline(),
+            "\
+Loona
+  LOOΠΔ 1/3 (100)
+    LOONA/yyxy (95)
+  LOOΠΔ / ODD EYE CIRCLE (86)"
+        );
+    }
+
     #[tokio::test]
     async fn child_similarity() {
         let pool = &TestPool::new(Some(&LASTFM_KEY)).await.pool;
@@ -287,4 +873,27 @@ mod tests {
         // non-deterministic
         assert!((50..=55).contains(&sim));
     }
+
+    #[test]
+    fn path() {
+        let graph = serde_json::json!({
+            "edge_property":"directed",
+            "edges":[[0,1,100],[1,2,95],[0,3,86]],
+            "nodes":["Loona","LOOΠΔ 1/3","LOONA/yyxy","LOOΠΔ / ODD EYE CIRCLE"]
+        });
+
+        let mut tree = ArtistTree::new("Loona");
+        tree.graph = serde_json::from_value(graph).unwrap();
+
+        assert_eq!(
+            tree.get_path("LOONA/yyxy").unwrap(),
+            [
+                ("Loona".to_string(), 100),
+                ("LOOΠΔ 1/3".to_string(), 100),
+                ("LOONA/yyxy".to_string(), 95),
+            ]
+        );
+        assert_eq!(tree.get_path("Loona").unwrap(), [("Loona".to_string(), 100)]);
+        assert!(tree.get_path("Metallica").is_none());
+    }
 }
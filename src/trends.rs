@@ -0,0 +1,282 @@
+//! Module for comparing snapshots of a user's chart over time. Snapshots are
+//! stored whenever a chart is fetched (at most one per day), so trends only
+//! become visible after a chart has been viewed on at least two different
+//! days.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use maud::html;
+use maud::Markup;
+use serde_json::json;
+use serde_json::Value;
+use strum::IntoEnumIterator;
+use urlencoding::encode;
+
+use crate::charts::Period;
+use crate::get_snapshot;
+use crate::get_snapshot_dates;
+use crate::html;
+use crate::SnapshotRow;
+use crate::SqPool;
+
+#[derive(Debug, PartialEq)]
+pub enum Movement {
+    New,
+    /// Number of ranks gained
+    Up(i64),
+    /// Number of ranks lost
+    Down(i64),
+    Same,
+}
+
+impl Display for Movement {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        match self {
+            Self::New => write!(f, "new"),
+            Self::Up(n) => write!(f, "↑{n}"),
+            Self::Down(n) => write!(f, "↓{n}"),
+            Self::Same => write!(f, "="),
+        }
+    }
+}
+
+pub struct Trend {
+    pub row: SnapshotRow,
+    pub movement: Movement,
+    /// Plays gained since the previous snapshot
+    pub plays: Option<i64>,
+}
+
+/// Compare the current snapshot to the previous one. Returns trends (in the
+/// order of `curr`) and dropouts (artists no longer in the chart).
+///
+/// Snapshots may contain different numbers of pages, so only ranks present in
+/// both snapshots are compared; otherwise, an extra page would show up as a
+/// wave of new entries (or dropouts).
+fn compare_snapshots(
+    prev: &[SnapshotRow],
+    curr: &[SnapshotRow],
+) -> (Vec<Trend>, Vec<SnapshotRow>) {
+    let cutoff = prev
+        .iter()
+        .map(|r| r.rank)
+        .max()
+        .unwrap_or(0)
+        .min(curr.iter().map(|r| r.rank).max().unwrap_or(0));
+
+    let prev_map: HashMap<&str, &SnapshotRow> = prev
+        .iter()
+        .filter(|r| r.rank <= cutoff)
+        .map(|r| (r.artist.as_str(), r))
+        .collect();
+
+    let trends = curr
+        .iter()
+        .filter(|r| r.rank <= cutoff)
+        .map(|r| {
+            let (movement, plays) = match prev_map.get(r.artist.as_str()) {
+                None => (Movement::New, None),
+                Some(p) => (
+                    match p.rank - r.rank {
+                        0 => Movement::Same,
+                        d if d > 0 => Movement::Up(d),
+                        d => Movement::Down(-d),
+                    },
+                    Some(r.playcount - p.playcount),
+                ),
+            };
+            Trend {
+                row: r.clone(),
+                movement,
+                plays,
+            }
+        })
+        .collect();
+
+    let dropouts = prev
+        .iter()
+        .filter(|p| p.rank <= cutoff)
+        .filter(|p| !curr.iter().any(|r| r.rank <= cutoff && r.artist == p.artist))
+        .cloned()
+        .collect();
+
+    (trends, dropouts)
+}
+
+pub struct Trends {
+    user: String,
+    period: Period,
+
+    /// (previous, current)
+    dates: Option<(String, String)>,
+    trends: Vec<Trend>,
+    dropouts: Vec<SnapshotRow>,
+}
+
+impl Trends {
+    /// Compare the two most recent snapshots
+    pub async fn new(
+        username: &str,
+        period: Period,
+        pool: &SqPool,
+    ) -> sqlx::Result<Self> {
+        let mut trends = Self {
+            user: username.to_string(),
+            period,
+            dates: None,
+            trends: vec![],
+            dropouts: vec![],
+        };
+
+        let dates = get_snapshot_dates(pool, username, period).await?;
+        let [curr_date, prev_date, ..] = dates.as_slice() else {
+            return Ok(trends);
+        };
+
+        let prev = get_snapshot(pool, username, period, prev_date).await?;
+        let curr = get_snapshot(pool, username, period, curr_date).await?;
+        (trends.trends, trends.dropouts) = compare_snapshots(&prev, &curr);
+        trends.dates = Some((prev_date.to_string(), curr_date.to_string()));
+
+        Ok(trends)
+    }
+
+    /// Used by the JSON API. `change` is the number of ranks gained (negative
+    /// if lost), and is omitted for new entries.
+    pub fn as_json(&self) -> Value {
+        let row = |r: &SnapshotRow| {
+            json!({ "artist": r.artist, "rank": r.rank, "playcount": r.playcount })
+        };
+        let trends: Vec<Value> = self
+            .trends
+            .iter()
+            .map(|t| {
+                let mut json = row(&t.row);
+                json["plays"] = json!(t.plays);
+                match t.movement {
+                    Movement::New => json["new"] = json!(true),
+                    Movement::Up(n) => json["change"] = json!(n),
+                    Movement::Down(n) => json["change"] = json!(-n),
+                    Movement::Same => json["change"] = json!(0),
+                }
+                json
+            })
+            .collect();
+        json!({
+            "user": self.user,
+            "period": self.period.to_string(),
+            "dates": self
+                .dates
+                .as_ref()
+                .map(|(prev, curr)| json!({ "previous": prev, "current": curr })),
+            "trends": trends,
+            "dropouts": self.dropouts.iter().map(row).collect::<Vec<_>>(),
+        })
+    }
+
+    pub fn as_html(&self) -> Markup {
+        let user = &self.user;
+
+        let periods = html! {
+            @for p in Period::iter() {
+                @if p == self.period {
+                    b { (p) }
+                } @else {
+                    (html::link(&format!("/charts/{user}/trends?period={p}"), &p.to_string()))
+                }
+                " "
+            }
+        };
+
+        let artist_link =
+            |name: &str| html::link(&format!("/artists/{}", encode(name)), name).into_string();
+
+        html! {
+            style {
+                "table, th, td { border: 1px solid grey; }"
+            }
+            (html::header(&format!("Trends for {user}")))
+            p { (html::link(&format!("/charts/{user}/{}", self.period), "Chart")) }
+            (periods)
+
+            @match &self.dates {
+                None => p {
+                    "Not enough snapshots yet. A snapshot is stored (at most once a day) "
+                    "whenever the chart is viewed."
+                },
+                Some((prev, curr)) => {
+                    p { (prev) " → " (curr) }
+                    table {
+                        th { "#" }
+                        th { "" }
+                        th { "Artist" }
+                        th { "Plays" }
+                        th { "New plays" }
+                        @for t in self.trends.iter() {
+                            @let cols = vec![
+                                t.row.rank.to_string(),
+                                t.movement.to_string(),
+                                artist_link(&t.row.artist),
+                                t.row.playcount.to_string(),
+                                t.plays.map(|p| format!("+{p}")).unwrap_or_default(),
+                            ];
+                            (html::table_row(cols))
+                        }
+                    }
+                    @if !self.dropouts.is_empty() {
+                        h3 { "Dropouts" }
+                        ul {
+                            @for d in self.dropouts.iter() {
+                                (html::list_item(&format!("{} (was #{})", artist_link(&d.artist), d.rank)))
+                            }
+                        }
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::compare_snapshots;
+    use super::Movement;
+    use crate::SnapshotRow;
+
+    fn snapshot(artists: &[&str]) -> Vec<SnapshotRow> {
+        artists
+            .iter()
+            .enumerate()
+            .map(|(i, a)| SnapshotRow {
+                artist: a.to_string(),
+                rank: i as i64 + 1,
+                playcount: 100 - i as i64,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_compare_snapshots() {
+        let prev = snapshot(&["A", "B", "C", "D"]);
+        // extra page; "F" should be ignored
+        let curr = snapshot(&["B", "A", "E", "D", "F"]);
+
+        let (trends, dropouts) = compare_snapshots(&prev, &curr);
+        let movements: Vec<Movement> = trends.into_iter().map(|t| t.movement).collect();
+        assert_eq!(
+            movements,
+            [
+                Movement::Up(1),
+                Movement::Down(1),
+                Movement::New,
+                Movement::Same
+            ]
+        );
+        assert_eq!(dropouts.len(), 1);
+        assert_eq!(dropouts[0].artist, "C");
+    }
+}
@@ -1,11 +1,25 @@
 // see also: num-format
 
+use std::time::Duration;
+use std::time::Instant;
+
 use reqwest::Url;
+use tokio::sync::Mutex;
 
 use crate::LASTFM_URL;
 
 const MILLION: f64 = 1_000_000.0;
 
+/// "Your account may be suspended if your application is continuously making
+/// several calls per second" -- 5 per second is the commonly cited limit
+///
+/// https://www.last.fm/api/tos
+const MIN_INTERVAL: Duration = Duration::from_millis(200);
+
+lazy_static::lazy_static! {
+    static ref LAST_REQUEST: Mutex<Option<Instant>> = Mutex::new(None);
+}
+
 pub fn human_number(num: u32) -> String {
     match (num as f64).log10().floor() as usize {
         0..=2 => num.to_string(),
@@ -34,6 +48,25 @@ pub fn build_lastfm_url(
     Ok(url)
 }
 
+/// Global rate limiter for last.fm requests. Requests are serialised, so this
+/// should only be awaited immediately before a request.
+pub async fn throttle() {
+    let mut last = LAST_REQUEST.lock().await;
+    if let Some(t) = *last {
+        let elapsed = t.elapsed();
+        if elapsed < MIN_INTERVAL {
+            tokio::time::sleep(MIN_INTERVAL - elapsed).await;
+        }
+    }
+    *last = Some(Instant::now());
+}
+
+/// Rate-limited GET, returning the response body
+pub async fn fetch(url: Url) -> reqwest::Result<String> {
+    throttle().await;
+    reqwest::get(url).await?.text().await
+}
+
 #[cfg(test)]
 mod tests {
     use crate::utils::human_number;